@@ -4,110 +4,1527 @@ use std::ffi::CString;
 
 use crate::api::ffi::*;
 use crate::api::ffi::{PxcBuffer, PxcResult};
-use std::ffi::{c_double, c_uint};
+use crate::api::units::{KeV, Seconds, Volts};
+use crate::data_worker::frame::Frame;
+use crate::data_worker::threshold::Thresholds;
+use std::ffi::{c_double, c_int, c_short, c_uint, c_ushort};
+use std::ops::ControlFlow;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
 
+/// Detector the frames are read from
+///
+/// The hardware devices only hold a handle to their pxcore device and settings and every pxcore call
+/// runs on the pxcore owner thread, so they are safe to use from any thread.
+/// The captured images are raw, `Frame::capture` applies the software thresholds
 pub trait Device: Send + Sync {
     fn capture_image(&self) -> PxcResult<PxcBuffer>;
     fn save_last_frame(&self, file_path: &str) -> PxcResult<()>;
     fn get_dimensions(&self) -> (c_uint, c_uint);
 
-    fn get_voltage_range(&self) -> PxcResult<(c_double, c_double)>;
-    fn set_high_voltage(&self, voltage: c_double) -> PxcResult<()>;
-    fn set_threshold(&self, threshold: c_double) -> PxcResult<()>;
-    fn set_frame_time(&mut self, seconds: c_double) -> PxcResult<()>;
+    fn get_voltage_range(&self) -> PxcResult<(Volts, Volts)>;
+    /// `PxcErr::InvalidArgument` if the voltage is outside of `get_voltage_range`
+    fn set_high_voltage(&mut self, voltage: Volts) -> PxcResult<()>;
+    /// bias set last, `None` until one was set
+    fn get_high_voltage(&self) -> Option<Volts>;
+    fn set_threshold(&mut self, threshold: KeV) -> PxcResult<()>;
+    /// hardware threshold set last, `None` until one was set
+    fn get_threshold(&self) -> Option<KeV>;
+    /// `PxcErr::InvalidArgument` if the frame time fails `Seconds::check_frame_time`
+    fn set_frame_time(&mut self, seconds: Seconds) -> PxcResult<()>;
+    fn get_frame_time(&self) -> Seconds;
 
-    fn set_software_high_threshold(&mut self, high_threshold: f64);
-    fn set_software_low_threshold(&mut self, low_threshold: f64);
+    /// software thresholds of the frames captured from the device
+    fn thresholds(&self) -> &Thresholds;
+    fn thresholds_mut(&mut self) -> &mut Thresholds;
+
+    fn set_software_high_threshold(&mut self, high_threshold: f64) {
+        self.thresholds_mut().high = high_threshold;
+    }
+    fn set_software_low_threshold(&mut self, low_threshold: f64) {
+        self.thresholds_mut().low = low_threshold;
+    }
+    fn get_software_high_threshold(&self) -> f64 {
+        self.thresholds().high
+    }
+    fn get_software_low_threshold(&self) -> f64 {
+        self.thresholds().low
+    }
+
+    /// every setting the device is running with
+    fn settings(&self) -> DeviceSettings {
+        DeviceSettings {
+            frame_time: self.get_frame_time(),
+            high_voltage: self.get_high_voltage(),
+            threshold: self.get_threshold(),
+            low_threshold: self.get_software_low_threshold(),
+            high_threshold: self.get_software_high_threshold(),
+            mode: self.get_mode(),
+            trigger: self.get_trigger(),
+            second_threshold: self.get_indexed_threshold(1),
+            charge_summing: self.get_charge_summing(),
+            tpx2_mode: self.get_tpx2_mode(),
+        }
+    }
+
+    /// measures `count` frames back to back, handing every readout to `on_frame` once it is read out
+    ///
+    /// devices without a continuous readout capture the frames one by one
+    ///
+    /// `on_frame` ends the capture early with `ControlFlow::Break`, once the frame it got is read out
+    fn capture_frames(
+        &self,
+        count: usize,
+        on_frame: &mut dyn FnMut(Readout) -> ControlFlow<()>,
+    ) -> PxcResult<()> {
+        capture_one_by_one(self, count, on_frame)
+    }
+    /// every image of a single acquisition
+    fn capture_readout(&self) -> PxcResult<Readout> {
+        if self.is_dual_channel() {
+            let (image, second) = self.capture_dual_image()?;
+            return Ok(Readout {
+                image,
+                second: Some(second),
+                energy: None,
+                toa: None,
+            });
+        }
+        if self.is_calibrated() {
+            return Ok(Readout::calibrated(self.capture_energy()?, None));
+        }
+        Ok(Readout::new(self.capture_image()?))
+    }
+
+    /// interrupts the frame being measured, which then fails with `PxcErr::AcqAborted`
+    ///
+    /// does not wait for the device, so it can be called during a capture
+    fn abort(&self) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+
+    /// what starts a measurement, frames are measured right away by default
+    fn set_trigger(&mut self, _trigger: TriggerMode) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+    fn get_trigger(&self) -> TriggerMode {
+        TriggerMode::None
+    }
+    /// whether a measurement is waiting for `software_trigger`
+    fn is_ready_for_trigger(&self) -> PxcResult<bool> {
+        Err(PxcErr::NotSupported.into())
+    }
+    /// starts the measurement waiting for a software trigger
+    ///
+    /// does not wait for the device, so it can be called during a capture
+    fn software_trigger(&self) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+
+    /// whether the device reads out single pixel hits instead of whole frames
+    fn is_data_driven(&self) -> bool {
+        false
+    }
+    /// pixel hits measured during one frame time, only on data driven devices
+    fn capture_hits(&self) -> PxcResult<Vec<PixelHit>> {
+        Err(PxcErr::NotSupported.into())
+    }
+
+    /// sets one of the hardware thresholds of devices with more than one
+    ///
+    /// threshold 0 is the one set by `set_threshold`
+    fn set_indexed_threshold(&mut self, threshold_index: c_int, threshold: KeV) -> PxcResult<()> {
+        match threshold_index {
+            0 => self.set_threshold(threshold),
+            _ => Err(PxcErr::NotSupported.into()),
+        }
+    }
+    /// hardware threshold set last, `None` until one was set and on devices without it
+    fn get_indexed_threshold(&self, threshold_index: c_int) -> Option<KeV> {
+        match threshold_index {
+            0 => self.get_threshold(),
+            _ => None,
+        }
+    }
+    fn set_charge_summing(&mut self, _enabled: bool) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+    /// `None` on devices without charge summing
+    fn get_charge_summing(&self) -> Option<bool> {
+        None
+    }
+
+    /// what the pixel values of the first and the optional second image mean
+    fn channels(&self) -> (Channel, Option<Channel>) {
+        (Channel::Tot, None)
+    }
+    /// whether every acquisition produces two images
+    fn is_dual_channel(&self) -> bool {
+        self.channels().1.is_some()
+    }
+    /// both images of a single acquisition, only on dual channel devices
+    ///
+    /// the second image is not limited to 16 bits, time of arrival does not fit
+    fn capture_dual_image(&self) -> PxcResult<(PxcBuffer, Vec<f64>)> {
+        Err(PxcErr::NotSupported.into())
+    }
+    fn set_tpx2_mode(&mut self, _mode: Tpx2Mode) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+    /// `None` on devices other than Timepix2
+    fn get_tpx2_mode(&self) -> Option<Tpx2Mode> {
+        None
+    }
+
+    /// whether the device counts hits, measures energy or time of arrival
+    fn set_mode(&mut self, _mode: TpxMode) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+    /// whether `capture_energy` returns deposited energy in keV
+    fn is_calibrated(&self) -> bool {
+        false
+    }
+    /// per pixel deposited energy in keV, only on calibrated devices
+    ///
+    /// the software thresholds of its frames are in keV as well
+    fn capture_energy(&self) -> PxcResult<Vec<f64>> {
+        Err(PxcErr::NotSupported.into())
+    }
+
+    /// sensor temperature in °C
+    fn get_temperature(&self) -> PxcResult<f64> {
+        Err(PxcErr::NotSupported.into())
+    }
+    /// bias voltage measured on the sensor, in V
+    fn get_bias_voltage(&self) -> PxcResult<f64> {
+        Err(PxcErr::NotSupported.into())
+    }
+    /// leakage current through the sensor, in µA
+    fn get_leakage_current(&self) -> PxcResult<f64> {
+        Err(PxcErr::NotSupported.into())
+    }
+    /// every reading the device supports, the others are `None`
+    ///
+    /// other errors, like a pxcore missing the getter, are returned
+    fn telemetry(&self) -> PxcResult<Telemetry> {
+        let supported = |reading: PxcResult<f64>| match reading {
+            Err(why) if why == PxcErr::NotSupported => Ok(None),
+            reading => reading.map(Some),
+        };
+        Ok(Telemetry {
+            temperature: supported(self.get_temperature())?,
+            bias_voltage: supported(self.get_bias_voltage())?,
+            leakage_current: supported(self.get_leakage_current())?,
+        })
+    }
+
+    /// mode matching the values of the first image
+    fn get_mode(&self) -> TpxMode {
+        match self.channels().0 {
+            Channel::Count => TpxMode::Medipix,
+            Channel::Tot => TpxMode::Tot,
+            Channel::Toa => TpxMode::Timepix,
+        }
+    }
+}
+
+/// captures the frames with an acquisition each, for devices without a continuous readout
+fn capture_one_by_one<D: Device + ?Sized>(
+    device: &D,
+    count: usize,
+    on_frame: &mut dyn FnMut(Readout) -> ControlFlow<()>,
+) -> PxcResult<()> {
+    for _ in 0..count {
+        if on_frame(device.capture_readout()?).is_break() {
+            break;
+        }
+    }
+    Ok(())
 }
 
+/// images read out of a single acquisition
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Readout {
+    /// first image, the energy rounded to whole keV on calibrated devices
+    pub image: PxcBuffer,
+    /// second image of dual channel devices, not limited to 16 bits
+    pub second: Option<Vec<f64>>,
+    /// deposited energy in keV, only on calibrated devices
+    pub energy: Option<Vec<f64>>,
+    /// time of arrival in ns, only in modes measuring nothing but the ToA,
+    /// the image holds it saturated at 16 bits
+    pub toa: Option<Vec<f64>>,
+}
+
+impl Readout {
+    pub fn new(image: PxcBuffer) -> Self {
+        Self {
+            image,
+            ..Default::default()
+        }
+    }
+
+    /// readout of a calibrated device, the image holds the rounded energy
+    pub fn calibrated(energy: Vec<f64>, second: Option<Vec<f64>>) -> Self {
+        Self {
+            image: energy.iter().map(|&kev| kev.round() as c_short).collect(),
+            second,
+            energy: Some(energy),
+            toa: None,
+        }
+    }
+}
+
+/// meaning of the pixel values of an image
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Channel {
+    /// amount of hits
+    Count,
+    /// time over threshold, energy deposited
+    #[default]
+    Tot,
+    /// time of arrival
+    Toa,
+}
+
+/// sensor readings of a device, `None` if the device can not measure them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Telemetry {
+    /// °C
+    pub temperature: Option<f64>,
+    /// V
+    pub bias_voltage: Option<f64>,
+    /// µA
+    pub leakage_current: Option<f64>,
+}
+
+/// settings a device is running with, see `Device::settings`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceSettings {
+    pub frame_time: Seconds,
+    /// `None` until set and on devices without a sensor
+    pub high_voltage: Option<Volts>,
+    pub threshold: Option<KeV>,
+    /// software thresholds, in keV on calibrated devices
+    pub low_threshold: f64,
+    pub high_threshold: f64,
+    pub mode: TpxMode,
+    pub trigger: TriggerMode,
+    /// settings only some devices have, `None` on the others
+    pub second_threshold: Option<KeV>,
+    pub charge_summing: Option<bool>,
+    pub tpx2_mode: Option<Tpx2Mode>,
+}
+
+impl DeviceSettings {
+    /// e.g. `{"frame_time":2,"high_voltage":40,"threshold":0.5,...,"mode":"tot","trigger":"none",...}`
+    ///
+    /// times are in s, voltages in V and the hardware thresholds in keV, unset values are `null`
+    pub fn to_json(&self) -> String {
+        format!(
+            concat!(
+                r#"{{"frame_time":{},"high_voltage":{},"threshold":{},"#,
+                r#""low_threshold":{},"high_threshold":{},"mode":"{}","trigger":"{}","#,
+                r#""second_threshold":{},"charge_summing":{},"tpx2_mode":{}}}"#
+            ),
+            json_number(Some(self.frame_time.0)),
+            json_number(self.high_voltage.map(|voltage| voltage.0)),
+            json_number(self.threshold.map(|threshold| threshold.0)),
+            json_number(Some(self.low_threshold)),
+            json_number(Some(self.high_threshold)),
+            self.mode,
+            self.trigger,
+            json_number(self.second_threshold.map(|threshold| threshold.0)),
+            self.charge_summing
+                .map_or(String::from("null"), |enabled| enabled.to_string()),
+            self.tpx2_mode
+                .map_or(String::from("null"), |mode| format!(r#""{mode}""#)),
+        )
+    }
+}
+
+/// json has no infinity or NaN, those become `null` as well
+fn json_number(value: Option<f64>) -> String {
+    match value {
+        Some(value) if value.is_finite() => value.to_string(),
+        _ => String::from("null"),
+    }
+}
+
+/// single pixel hit of a data driven device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelHit {
+    pub x: c_uint,
+    pub y: c_uint,
+    /// time of arrival in ns
+    pub toa: f64,
+    /// time over threshold, deposited energy in keV on calibrated devices
+    pub tot: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum TpxMode {
     /// counting mode
     Medipix = 0,
     /// energy mode
+    #[default]
     Tot = 1,
     /// timepix mode
     Timepix = 3,
 }
 
-pub struct TpxDevice {
-    pub index: std::ffi::c_uint,
-    pub frame_time: std::ffi::c_double,
-    pub dimensions: (std::ffi::c_uint, std::ffi::c_uint),
-    pub low_threshold: f64,
-    pub high_threshold: f64,
+impl TpxMode {
+    /// what the pixel values measured in this mode mean
+    pub fn channel(self) -> Channel {
+        match self {
+            TpxMode::Medipix => Channel::Count,
+            TpxMode::Tot => Channel::Tot,
+            TpxMode::Timepix => Channel::Toa,
+        }
+    }
 }
 
-impl Device for TpxDevice {
-    fn capture_image(&self) -> PxcResult<PxcBuffer> {
-        let mut data_buf: PxcBuffer = [0; 65536];
-        let mut size: std::ffi::c_uint = 65536;
-        unsafe {
-            pxcMeasureSingleFrame(self.index, self.frame_time, &mut data_buf, &mut size)
-                .check_rc()?;
-
-            if size == 0 {
-                size = 65536;
-                pxcGetMeasuredFrame(self.index, 0, &mut data_buf, &mut size).check_rc()?;
-            }
+/// the name `from_str` parses
+impl std::fmt::Display for TpxMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TpxMode::Medipix => write!(f, "medipix"),
+            TpxMode::Tot => write!(f, "tot"),
+            TpxMode::Timepix => write!(f, "timepix"),
         }
-        if self.high_threshold != 0.0 {
-            for val in data_buf.iter_mut() {
-                if *val > self.high_threshold as std::ffi::c_short {
-                    *val = 0;
+    }
+}
+
+impl std::str::FromStr for TpxMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "medipix" | "counting" => Ok(TpxMode::Medipix),
+            "tot" | "energy" => Ok(TpxMode::Tot),
+            "timepix" | "toa" => Ok(TpxMode::Timepix),
+            _ => Err(format!("Unknown mode '{s}'")),
+        }
+    }
+}
+
+/// what starts a measurement, pxcore's `trgStg`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TriggerMode {
+    /// starts right away
+    #[default]
+    None = 0,
+    /// starts on a pulse on the trigger input
+    HwStart = 1,
+    /// starts right away, ends on a pulse on the trigger input
+    HwStop = 2,
+    /// starts and ends on pulses on the trigger input
+    HwStartStop = 3,
+    /// starts on `software_trigger`
+    SwStart = 4,
+}
+
+/// the name `from_str` parses
+impl std::fmt::Display for TriggerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerMode::None => write!(f, "none"),
+            TriggerMode::HwStart => write!(f, "hw-start"),
+            TriggerMode::HwStop => write!(f, "hw-stop"),
+            TriggerMode::HwStartStop => write!(f, "hw-start-stop"),
+            TriggerMode::SwStart => write!(f, "sw-start"),
+        }
+    }
+}
+
+impl std::str::FromStr for TriggerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(TriggerMode::None),
+            "hw-start" => Ok(TriggerMode::HwStart),
+            "hw-stop" => Ok(TriggerMode::HwStop),
+            "hw-start-stop" => Ok(TriggerMode::HwStartStop),
+            "sw-start" => Ok(TriggerMode::SwStart),
+            _ => Err(format!("Unknown trigger '{s}'")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tpx3Mode {
+    /// time of arrival and time over threshold
+    ToaTot = 0,
+    /// time of arrival only
+    Toa = 1,
+    /// event counting and integrated time over threshold
+    EventItot = 2,
+    /// time over threshold only
+    TotNotoa = 3,
+}
+
+pub enum Mpx3Mode {
+    /// single pixel mode, one counter
+    SinglePixel = 0,
+    /// single pixel mode, both counters
+    SinglePixelDual = 1,
+    /// charge summing mode, one counter
+    ChargeSumming = 2,
+    /// charge summing mode, both counters
+    ChargeSummingDual = 3,
+}
+
+impl Mpx3Mode {
+    /// mode reading out both counters, with or without charge summing
+    pub fn reading_both_counters(charge_summing: bool) -> Self {
+        match charge_summing {
+            true => Mpx3Mode::ChargeSummingDual,
+            false => Mpx3Mode::SinglePixelDual,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tpx2Mode {
+    /// 10 bit ToT and 18 bit ToA at once
+    Tot10Toa18 = 0,
+    /// 14 bit ToT and 14 bit ToA at once
+    Tot14Toa14 = 1,
+    /// 10 bit ToT and 4 bit hit count at once
+    Tot10Count4 = 2,
+    /// 14 bit ToT only
+    Tot14 = 3,
+    /// 14 bit ToA only
+    Toa14 = 5,
+    /// 14 bit hit count only
+    Count14 = 6,
+}
+
+/// the name `from_str` parses
+impl std::fmt::Display for Tpx2Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tpx2Mode::Tot10Toa18 => write!(f, "tot-toa"),
+            Tpx2Mode::Tot14Toa14 => write!(f, "tot-toa-14"),
+            Tpx2Mode::Tot10Count4 => write!(f, "tot-count"),
+            Tpx2Mode::Tot14 => write!(f, "tot"),
+            Tpx2Mode::Toa14 => write!(f, "toa"),
+            Tpx2Mode::Count14 => write!(f, "count"),
+        }
+    }
+}
+
+impl std::str::FromStr for Tpx2Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tot-toa" => Ok(Tpx2Mode::Tot10Toa18),
+            "tot-toa-14" => Ok(Tpx2Mode::Tot14Toa14),
+            "tot-count" => Ok(Tpx2Mode::Tot10Count4),
+            "tot" => Ok(Tpx2Mode::Tot14),
+            "toa" => Ok(Tpx2Mode::Toa14),
+            "count" => Ok(Tpx2Mode::Count14),
+            _ => Err(format!("Unknown Timepix2 mode '{s}'")),
+        }
+    }
+}
+
+/// Settings and pxcore calls every pxcore device shares
+///
+/// the families only add their measurements and modes, see `pxcore_device_core`
+#[derive(Debug)]
+pub struct DeviceCore {
+    pub device: PxcDevice,
+    pub frame_time: c_double,
+    pub dimensions: (c_uint, c_uint),
+    pub thresholds: Thresholds,
+    /// bias and hardware threshold set last
+    pub high_voltage: Option<Volts>,
+    pub threshold: Option<KeV>,
+    pub trigger: TriggerMode,
+    /// stands in for the software trigger of blocking measurements, see `measure_blocking`
+    pub software_start: AbortSignal,
+}
+
+impl DeviceCore {
+    pub fn new(device: PxcDevice, frame_time: Seconds, dimensions: (c_uint, c_uint)) -> Self {
+        Self {
+            device,
+            frame_time: frame_time.0,
+            dimensions,
+            thresholds: Thresholds::default(),
+            high_voltage: None,
+            threshold: None,
+            trigger: TriggerMode::None,
+            software_start: AbortSignal::default(),
+        }
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        pixel_count(self.dimensions)
+    }
+
+    /// sets one of the hardware thresholds, only threshold 0 is kept as `threshold`
+    fn set_indexed_threshold(&mut self, threshold_index: c_int, threshold: KeV) -> PxcResult<()> {
+        self.device
+            .check(|index| unsafe { pxcSetThreshold(index, threshold_index, threshold.0) })?;
+        if threshold_index == 0 {
+            self.threshold = Some(threshold);
+        }
+        Ok(())
+    }
+
+    fn set_frame_time(&mut self, seconds: Seconds) -> PxcResult<()> {
+        self.frame_time = seconds.check_frame_time()?.0;
+        Ok(())
+    }
+
+    /// aborts the running measurement, also one waiting for the software trigger
+    fn abort(&self) -> PxcResult<()> {
+        self.software_start.abort();
+        self.device.abort()
+    }
+
+    /// measures `count` frames with a single continuous measurement,
+    /// the owner reads out every one with `read_frame` as soon as it is measured
+    ///
+    /// the trigger starts the whole series, see `Device::capture_frames` for `on_frame`
+    fn measure_frames(
+        &self,
+        count: usize,
+        read_frame: impl FnMut(c_uint) -> PxcResult<Readout> + Send + 'static,
+        on_frame: &mut dyn FnMut(Readout) -> ControlFlow<()>,
+    ) -> PxcResult<()> {
+        let frames = self.device.measure_continuous(
+            count,
+            self.frame_time,
+            self.trigger as c_uint,
+            read_frame,
+        )?;
+        let mut received = 0;
+        for readout in frames {
+            received += 1;
+            if on_frame(readout?).is_break() {
+                // only the frame which just started is lost
+                if received < count {
+                    self.device.abort()?;
                 }
+                return Ok(());
+            }
+        }
+        // the measurement ends early when it is aborted
+        match received < count {
+            true => Err(PxcError::from(PxcErr::AcqAborted).on_device(self.device.index)),
+            false => Ok(()),
+        }
+    }
+
+    /// measures a single frame with `measure_frames`
+    fn measure_frame(
+        &self,
+        read_frame: impl FnMut(c_uint) -> PxcResult<Readout> + Send + 'static,
+    ) -> PxcResult<Readout> {
+        let mut readout = None;
+        self.measure_frames(1, read_frame, &mut |frame| {
+            readout = Some(frame);
+            ControlFlow::Continue(())
+        })?;
+        readout.ok_or_else(|| PxcError::from(PxcErr::AcqFailed).on_device(self.device.index))
+    }
+
+    /// runs a measurement which blocks pxcore as a single call on the owner thread
+    ///
+    /// `measure` gets the index and the trigger to hand to pxcore. The software trigger is
+    /// waited for before the call is sent, so the owner never waits for it
+    fn measure_blocking<T: Send>(
+        &self,
+        measure: impl FnOnce(c_uint, c_uint) -> PxcResult<T>,
+    ) -> PxcResult<T> {
+        let trigger = match self.trigger {
+            TriggerMode::SwStart => {
+                self.software_start.wait_for_trigger()?;
+                TriggerMode::None
             }
+            trigger => trigger,
+        };
+        self.device.call(|index| measure(index, trigger as c_uint))
+    }
+
+    /// whether the measurement of the device waits for `software_trigger`
+    fn is_ready_for_trigger(&self) -> PxcResult<bool> {
+        if self.software_start.is_armed() {
+            return Ok(true);
         }
-        for val in data_buf.iter_mut() {
-            if *val < self.low_threshold as std::ffi::c_short {
-                *val = 0;
+        self.device.is_ready_for_trigger()
+    }
+
+    /// starts the measurement of the device waiting for a software trigger
+    fn software_trigger(&self) -> PxcResult<()> {
+        if self.software_start.is_armed() {
+            return self.software_start.trigger();
+        }
+        self.device.software_trigger()
+    }
+
+    /// bias range of the sensor as reported by pxcore
+    fn bias_range(&self) -> PxcResult<(Volts, Volts)> {
+        let mut min_voltage = 0.0;
+        let mut max_voltage = 0.0;
+        self.device
+            .check(|index| unsafe { pxcGetBiasRange(index, &mut min_voltage, &mut max_voltage) })?;
+        Ok((Volts(min_voltage), Volts(max_voltage)))
+    }
+
+    /// sets the bias after checking it against the range of the sensor
+    fn set_bias(&mut self, voltage: Volts) -> PxcResult<()> {
+        let voltage = voltage
+            .check_range(self.bias_range()?)
+            .map_err(|why| why.on_device(self.device.index))?;
+        self.device
+            .check(|index| unsafe { pxcSetBias(index, voltage.0) })?;
+        self.high_voltage = Some(voltage);
+        Ok(())
+    }
+
+    /// saves the first frame of the last measurement
+    fn save_last_frame(&self, file_path: &str) -> PxcResult<()> {
+        let c_file_path = CString::new(file_path).map_err(|_| PxcError {
+            message: Some(format!("invalid file path {file_path:?}")),
+            ..PxcError::from(PxcErr::InvalidArgument).on_device(self.device.index)
+        })?;
+        self.device
+            .check(|index| unsafe { pxcSaveMeasuredFrame(index, 0, c_file_path.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// reads a single value from one of the pxcore getters
+    fn read_sensor(&self, read: impl FnOnce(c_uint, *mut c_double) -> c_int) -> PxcResult<f64> {
+        let mut value: c_double = 0.0;
+        self.device.check(|index| read(index, &mut value))?;
+        Ok(value)
+    }
+
+    /// reads the first of the device parameters `names` the readout hardware has
+    ///
+    /// `PxcErr::NotSupported` if it has none of them
+    fn read_parameter(&self, names: &[&str]) -> PxcResult<f64> {
+        for name in names {
+            let c_name = CString::new(*name).unwrap();
+            let result = self.read_sensor(|index, value| unsafe {
+                pxcGetDeviceParameterDouble(index, c_name.as_ptr(), value)
+            });
+            match result {
+                // pxcore rejects parameters the hardware does not have
+                Err(why) if why == PxcErr::InvalidArgument => continue,
+                result => return result,
             }
         }
-        Ok(data_buf)
+        Err(PxcError {
+            message: Some(format!("no device parameter {}", names.join(" or "))),
+            ..PxcError::from(PxcErr::NotSupported).on_device(self.device.index)
+        })
+    }
+}
+
+/// device parameters with the chip temperature, named differently by the readout hardware
+const TEMPERATURE_PARAMETERS: &[&str] = &["TemperatureChip", "TempDet", "Temperature"];
+/// device parameters with the current through the sensor
+const LEAKAGE_CURRENT_PARAMETERS: &[&str] = &["BiasSenseCurrent", "BiasCurrentSense"];
+
+/// the `Device` methods every pxcore device implements through its `core: DeviceCore`
+macro_rules! pxcore_device_core {
+    () => {
+        fn get_dimensions(&self) -> (c_uint, c_uint) {
+            self.core.dimensions
+        }
+
+        fn set_threshold(&mut self, threshold: KeV) -> PxcResult<()> {
+            self.core.set_indexed_threshold(0, threshold)
+        }
+        fn get_threshold(&self) -> Option<KeV> {
+            self.core.threshold
+        }
+
+        fn set_frame_time(&mut self, seconds: Seconds) -> PxcResult<()> {
+            self.core.set_frame_time(seconds)
+        }
+        fn get_frame_time(&self) -> Seconds {
+            Seconds(self.core.frame_time)
+        }
+
+        fn abort(&self) -> PxcResult<()> {
+            self.core.abort()
+        }
+
+        fn set_trigger(&mut self, trigger: TriggerMode) -> PxcResult<()> {
+            self.core.trigger = trigger;
+            Ok(())
+        }
+        fn get_trigger(&self) -> TriggerMode {
+            self.core.trigger
+        }
+        fn is_ready_for_trigger(&self) -> PxcResult<bool> {
+            self.core.is_ready_for_trigger()
+        }
+        fn software_trigger(&self) -> PxcResult<()> {
+            self.core.software_trigger()
+        }
+
+        fn get_voltage_range(&self) -> PxcResult<(Volts, Volts)> {
+            self.core.bias_range()
+        }
+        fn set_high_voltage(&mut self, voltage: Volts) -> PxcResult<()> {
+            self.core.set_bias(voltage)
+        }
+        fn get_high_voltage(&self) -> Option<Volts> {
+            self.core.high_voltage
+        }
+
+        fn get_temperature(&self) -> PxcResult<f64> {
+            self.core.read_parameter(TEMPERATURE_PARAMETERS)
+        }
+        fn get_bias_voltage(&self) -> PxcResult<f64> {
+            self.core
+                .read_sensor(|index, value| unsafe { pxcGetBias(index, value) })
+        }
+        fn get_leakage_current(&self) -> PxcResult<f64> {
+            self.core.read_parameter(LEAKAGE_CURRENT_PARAMETERS)
+        }
+
+        fn thresholds(&self) -> &Thresholds {
+            &self.core.thresholds
+        }
+        fn thresholds_mut(&mut self) -> &mut Thresholds {
+            &mut self.core.thresholds
+        }
+    };
+}
+
+/// Timepix device
+///
+/// a trigger starts a whole series of frames, calibrated frames are measured one by one
+/// and every one of them waits for the trigger
+#[derive(Debug)]
+pub struct TpxDevice {
+    pub core: DeviceCore,
+    pub mode: TpxMode,
+    /// whether calibration is enabled and pxcore can measure calibrated frames
+    pub calibrated: bool,
+}
+
+impl TpxDevice {
+    /// reads out the raw frames of the running measurement on the owner thread
+    fn frame_reader(&self) -> impl FnMut(c_uint) -> PxcResult<Readout> + Send + 'static {
+        let index = self.core.device.index;
+        let pixel_count = self.core.pixel_count();
+        move |frame_index| {
+            let mut data_buf: PxcBuffer = Vec::new();
+            measure_resizing(index, pixel_count, |len, size| {
+                data_buf.resize(len, 0);
+                unsafe { pxcGetMeasuredFrame(index, frame_index, data_buf.as_mut_ptr(), size) }
+            })?;
+            data_buf.resize(pixel_count, 0);
+            Ok(Readout::new(data_buf))
+        }
+    }
+}
+
+impl Device for TpxDevice {
+    /// pxcore has no calibrated readout for Timepix, calibrated frames are measured one by one
+    fn capture_frames(
+        &self,
+        count: usize,
+        on_frame: &mut dyn FnMut(Readout) -> ControlFlow<()>,
+    ) -> PxcResult<()> {
+        match self.is_calibrated() {
+            true => capture_one_by_one(self, count, on_frame),
+            false => self
+                .core
+                .measure_frames(count, self.frame_reader(), on_frame),
+        }
+    }
+
+    fn capture_image(&self) -> PxcResult<PxcBuffer> {
+        Ok(self.core.measure_frame(self.frame_reader())?.image)
     }
 
     fn save_last_frame(&self, file_path: &str) -> PxcResult<()> {
-        unsafe {
-            let c_file_path: CString = CString::new(file_path).unwrap();
-            pxcSaveMeasuredFrame(self.index, 0, c_file_path.as_ptr()).check_rc()?;
+        self.core.save_last_frame(file_path)
+    }
+
+    pxcore_device_core!();
+
+    fn set_mode(&mut self, mode: TpxMode) -> PxcResult<()> {
+        self.core
+            .device
+            .check(|index| unsafe { pxcSetTimepixMode(index, mode as i32) })?;
+        self.mode = mode;
+        Ok(())
+    }
+    fn channels(&self) -> (Channel, Option<Channel>) {
+        (self.mode.channel(), None)
+    }
+
+    /// energy is only measured in ToT mode
+    fn is_calibrated(&self) -> bool {
+        self.calibrated && self.mode == TpxMode::Tot
+    }
+    /// measured as a single blocking call, an abort waits for the frame to end
+    fn capture_energy(&self) -> PxcResult<Vec<f64>> {
+        let pixel_count = self.core.pixel_count();
+        let frame_time = self.core.frame_time;
+        self.core.measure_blocking(|index, trigger| {
+            let mut energy = Vec::new();
+            measure_resizing(index, pixel_count, |len, size| {
+                energy.resize(len, 0.0);
+                unsafe {
+                    pxcMeasureSingleCalibratedFrame(
+                        index,
+                        frame_time,
+                        energy.as_mut_ptr(),
+                        size,
+                        trigger,
+                    )
+                }
+            })?;
+            energy.resize(pixel_count, 0.0);
+            Ok(energy)
+        })
+    }
+}
+
+/// Lets `abort` interrupt the frame time of devices without hardware
+///
+/// also stands in for the software trigger of those devices and of blocking measurements
+#[derive(Debug, Default)]
+pub struct AbortSignal {
+    state: Mutex<AbortState>,
+    wake: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct AbortState {
+    measuring: bool,
+    aborted: bool,
+    armed: bool,
+    triggered: bool,
+}
+
+impl AbortSignal {
+    /// interrupts the running `sleep`, does nothing if there is none
+    pub fn abort(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.measuring {
+            state.aborted = true;
+            self.wake.notify_all();
+        }
+    }
+
+    /// waits for the frame time, `PxcErr::AcqAborted` if it got interrupted
+    pub fn sleep(&self, duration: Duration) -> PxcResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.measuring = true;
+        state.aborted = false;
+        let (mut state, _) = self
+            .wake
+            .wait_timeout_while(state, duration, |state| !state.aborted)
+            .unwrap();
+        state.measuring = false;
+        match state.aborted {
+            true => Err(PxcErr::AcqAborted.into()),
+            false => Ok(()),
         }
+    }
+
+    /// waits for `trigger`, `PxcErr::AcqAborted` if it got aborted instead
+    pub fn wait_for_trigger(&self) -> PxcResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.measuring = true;
+        state.aborted = false;
+        state.armed = true;
+        state.triggered = false;
+        let mut state = self
+            .wake
+            .wait_while(state, |state| !state.aborted && !state.triggered)
+            .unwrap();
+        state.measuring = false;
+        state.armed = false;
+        match state.aborted {
+            true => Err(PxcErr::AcqAborted.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// whether `wait_for_trigger` is waiting
+    pub fn is_armed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.armed && !state.triggered
+    }
+
+    /// ends the running `wait_for_trigger`, `PxcErr::NotAllowed` if there is none
+    pub fn trigger(&self) -> PxcResult<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.armed {
+            return Err(PxcErr::NotAllowed.into());
+        }
+        state.triggered = true;
+        self.wake.notify_all();
         Ok(())
     }
+}
+
+/// how many retries a measurement gets when pxcore reports a too small buffer
+const BUFFER_RETRIES: usize = 3;
+
+pub fn pixel_count(dimensions: (c_uint, c_uint)) -> usize {
+    dimensions.0 as usize * dimensions.1 as usize
+}
 
-    fn get_dimensions(&self) -> (std::ffi::c_uint, std::ffi::c_uint) {
-        self.dimensions
+/// runs a measurement with buffers sized for the device
+///
+/// `measure` gets the length to resize its buffers to and the size passed to pxcore.
+/// When pxcore reports `BufferSmall` the buffers grow and the measurement is retried,
+/// callers should cut the buffers back to `pixel_count` afterwards
+fn measure_resizing(
+    index: c_uint,
+    pixel_count: usize,
+    mut measure: impl FnMut(usize, &mut c_uint) -> c_int,
+) -> PxcResult<()> {
+    let mut len = pixel_count.max(1);
+    for _ in 0..BUFFER_RETRIES {
+        let mut size = len as c_uint;
+        match measure(len, &mut size).check_device(index) {
+            Err(why) if why == PxcErr::BufferSmall => {
+                len = (size as usize).max(len * 2);
+            }
+            result => return result.map(|_| ()),
+        }
     }
+    Err(PxcError::from(PxcErr::BufferSmall).on_device(index))
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+pub struct Tpx3Pixel {
+    index: u32,
+    toa: f64,
+    tot: f32,
+}
 
-    fn set_threshold(&self, threshold: c_double) -> PxcResult<()> {
-        unsafe {
-            pxcSetThreshold(self.index, 0, threshold).check_rc()?;
+/// Timepix3 device in data driven mode
+///
+/// Every frame time it measures a stream of pixel hits,
+/// `capture_image` turns them into an image of the measured quantity for devices which expect frames
+///
+/// a trigger starts the stream of hits of every frame time
+#[derive(Debug)]
+pub struct Tpx3Device {
+    pub core: DeviceCore,
+    pub mode: Tpx3Mode,
+    /// whether the ToT of the hits is converted to keV
+    pub calibrated: bool,
+}
+
+/// state shared with `collect_hits` during a data driven measurement
+struct HitCollector {
+    index: c_uint,
+    width: c_uint,
+    hits: Vec<PixelHit>,
+    error: Option<PxcError>,
+}
+
+/// fetches the pixels pxcore measured since the last call
+unsafe extern "C" fn collect_hits(_event_data: isize, user_data: isize) {
+    let collector = unsafe { &mut *(user_data as *mut HitCollector) };
+    in_callback(|| collector.collect());
+}
+
+impl HitCollector {
+    fn collect(&mut self) {
+        let mut count: c_uint = 0;
+        let mut pixels = Vec::new();
+        let result = unsafe {
+            pxcGetMeasuredTpx3PixelsCount(self.index, &mut count)
+                .check_device(self.index)
+                .and_then(|_| {
+                    pixels.resize(count as usize, Tpx3Pixel::default());
+                    pxcGetMeasuredTpx3Pixels(self.index, pixels.as_mut_ptr(), count)
+                        .check_device(self.index)
+                })
+        };
+        if let Err(why) = result {
+            self.error = Some(why);
+            return;
         }
+
+        let width = self.width.max(1);
+        self.hits.extend(pixels.into_iter().map(|pixel| PixelHit {
+            x: pixel.index % width,
+            y: pixel.index / width,
+            toa: pixel.toa,
+            tot: pixel.tot,
+        }));
+    }
+}
+
+impl Device for Tpx3Device {
+    fn capture_image(&self) -> PxcResult<PxcBuffer> {
+        let hits = self.capture_hits()?;
+        let frame = Frame::from_hits(hits, self.core.dimensions, self.channels().0);
+        Ok(frame.data.concat())
+    }
+
+    fn save_last_frame(&self, _file_path: &str) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+
+    pxcore_device_core!();
+
+    fn is_data_driven(&self) -> bool {
+        true
+    }
+
+    /// energy mode measures ToA along with ToT, every hit has both
+    fn set_mode(&mut self, mode: TpxMode) -> PxcResult<()> {
+        let mode = match mode {
+            TpxMode::Medipix => Tpx3Mode::EventItot,
+            TpxMode::Tot => Tpx3Mode::ToaTot,
+            TpxMode::Timepix => Tpx3Mode::Toa,
+        };
+        self.core
+            .device
+            .check(|index| unsafe { pxcSetTimepix3Mode(index, mode as i32) })?;
+        self.mode = mode;
         Ok(())
     }
+    fn channels(&self) -> (Channel, Option<Channel>) {
+        match self.mode {
+            Tpx3Mode::EventItot => (Channel::Count, None),
+            Tpx3Mode::ToaTot | Tpx3Mode::TotNotoa => (Channel::Tot, None),
+            Tpx3Mode::Toa => (Channel::Toa, None),
+        }
+    }
+
+    /// the energy is carried by the ToT of the hits
+    fn is_calibrated(&self) -> bool {
+        self.calibrated && self.channels().0 == Channel::Tot
+    }
 
-    fn set_frame_time(&mut self, seconds: c_double) -> PxcResult<()> {
-        self.frame_time = seconds;
+    /// the measurement is a single blocking call, reading the hits out in its callback
+    fn capture_hits(&self) -> PxcResult<Vec<PixelHit>> {
+        let mut collector = HitCollector {
+            index: self.core.device.index,
+            width: self.core.dimensions.0,
+            hits: Vec::new(),
+            error: None,
+        };
+        // empty file name, the hits are only handed to the callback
+        let file_name = CString::new("").unwrap();
+        let frame_time = self.core.frame_time;
+        self.core.measure_blocking(|index, trigger| {
+            unsafe {
+                pxcMeasureTpx3DataDrivenMode(
+                    index,
+                    frame_time,
+                    file_name.as_ptr(),
+                    trigger,
+                    Some(collect_hits),
+                    &mut collector as *mut HitCollector as isize,
+                )
+            }
+            .check_device(index)
+        })?;
+        if let Some(why) = collector.error {
+            return Err(why);
+        }
+        Ok(collector.hits)
+    }
+}
+
+/// Medipix3 device reading out both counters
+///
+/// The first counter counts above threshold 0, the second above threshold 1
+///
+/// a trigger starts a whole series of frames
+#[derive(Debug)]
+pub struct Mpx3Device {
+    pub core: DeviceCore,
+    pub charge_summing: bool,
+    /// threshold 1 set last
+    pub second_threshold: Option<KeV>,
+}
+
+impl Mpx3Device {
+    pub fn operation_mode(&self) -> Mpx3Mode {
+        Mpx3Mode::reading_both_counters(self.charge_summing)
+    }
+
+    /// reads out both counters of the frames of the running measurement on the owner thread
+    fn frame_reader(&self) -> impl FnMut(c_uint) -> PxcResult<Readout> + Send + 'static {
+        let index = self.core.device.index;
+        let pixel_count = self.core.pixel_count();
+        move |frame_index| Self::read_frame(index, pixel_count, frame_index)
+    }
+
+    fn read_frame(index: c_uint, pixel_count: usize, frame_index: c_uint) -> PxcResult<Readout> {
+        let mut counter_1: Vec<c_uint> = Vec::new();
+        let mut counter_2: Vec<c_uint> = Vec::new();
+        measure_resizing(index, pixel_count, |len, size| {
+            counter_1.resize(len, 0);
+            counter_2.resize(len, 0);
+            unsafe {
+                pxcGetMeasuredFrameMpx3(
+                    index,
+                    frame_index,
+                    counter_1.as_mut_ptr(),
+                    counter_2.as_mut_ptr(),
+                    size,
+                )
+            }
+        })?;
+
+        let [first, second] = [counter_1, counter_2].map(|counter| {
+            let mut data_buf: PxcBuffer = counter
+                .into_iter()
+                .take(pixel_count)
+                .map(|count| count.min(c_short::MAX as c_uint) as c_short)
+                .collect();
+            data_buf.resize(pixel_count, 0);
+            data_buf
+        });
+        Ok(Readout {
+            image: first,
+            second: Some(second.iter().map(|&val| val as f64).collect()),
+            energy: None,
+            toa: None,
+        })
+    }
+}
+
+impl Device for Mpx3Device {
+    fn capture_frames(
+        &self,
+        count: usize,
+        on_frame: &mut dyn FnMut(Readout) -> ControlFlow<()>,
+    ) -> PxcResult<()> {
+        self.core
+            .measure_frames(count, self.frame_reader(), on_frame)
+    }
+    fn capture_readout(&self) -> PxcResult<Readout> {
+        self.core.measure_frame(self.frame_reader())
+    }
+
+    /// only the first counter, see `capture_dual_image`
+    fn capture_image(&self) -> PxcResult<PxcBuffer> {
+        Ok(self.capture_readout()?.image)
+    }
+
+    fn save_last_frame(&self, file_path: &str) -> PxcResult<()> {
+        self.core.save_last_frame(file_path)
+    }
+
+    pxcore_device_core!();
+
+    fn set_indexed_threshold(&mut self, threshold_index: c_int, threshold: KeV) -> PxcResult<()> {
+        self.core
+            .set_indexed_threshold(threshold_index, threshold)?;
+        if threshold_index == 1 {
+            self.second_threshold = Some(threshold);
+        }
         Ok(())
     }
+    fn get_indexed_threshold(&self, threshold_index: c_int) -> Option<KeV> {
+        match threshold_index {
+            0 => self.core.threshold,
+            1 => self.second_threshold,
+            _ => None,
+        }
+    }
+    fn set_charge_summing(&mut self, enabled: bool) -> PxcResult<()> {
+        let mode = Mpx3Mode::reading_both_counters(enabled);
+        self.core
+            .device
+            .check(|index| unsafe { pxcSetMedipix3OperationMode(index, mode as i32) })?;
+        self.charge_summing = enabled;
+        Ok(())
+    }
+    fn get_charge_summing(&self) -> Option<bool> {
+        Some(self.charge_summing)
+    }
 
-    fn get_voltage_range(&self) -> PxcResult<(c_double, c_double)> {
-        let mut min_voltage = 0.0;
-        let mut max_voltage = 0.0;
-        unsafe {
-            pxcGetBiasRange(self.index, &mut min_voltage, &mut max_voltage).check_rc()?;
+    fn channels(&self) -> (Channel, Option<Channel>) {
+        (Channel::Count, Some(Channel::Count))
+    }
+    fn capture_dual_image(&self) -> PxcResult<(PxcBuffer, Vec<f64>)> {
+        let readout = self.capture_readout()?;
+        Ok((readout.image, readout.second.unwrap_or_default()))
+    }
+}
+
+/// Timepix2 device, measuring two quantities at once in the combined modes
+///
+/// a trigger starts a whole series of frames
+#[derive(Debug)]
+pub struct Tpx2Device {
+    pub core: DeviceCore,
+    pub mode: Tpx2Mode,
+    pub calibrated: bool,
+}
+
+impl Tpx2Device {
+    /// reads out the frames of the running measurement on the owner thread,
+    /// in keV on calibrated devices
+    fn frame_reader(&self) -> impl FnMut(c_uint) -> PxcResult<Readout> + Send + 'static {
+        let index = self.core.device.index;
+        let pixel_count = self.core.pixel_count();
+        let (mode, calibrated, dual) = (self.mode, self.is_calibrated(), self.is_dual_channel());
+        move |frame_index| Self::read_frame(index, pixel_count, mode, calibrated, dual, frame_index)
+    }
+
+    fn read_frame(
+        index: c_uint,
+        pixel_count: usize,
+        mode: Tpx2Mode,
+        calibrated: bool,
+        dual: bool,
+        frame_index: c_uint,
+    ) -> PxcResult<Readout> {
+        let mut toa_itot: Vec<c_double> = Vec::new();
+        let second = |toa_itot| dual.then_some(toa_itot);
+
+        if calibrated {
+            let mut energy = Vec::new();
+            measure_resizing(index, pixel_count, |len, size| {
+                energy.resize(len, 0.0);
+                toa_itot.resize(len, 0.0);
+                unsafe {
+                    pxcGetMeasuredCalibratedFrameTpx2(
+                        index,
+                        frame_index,
+                        toa_itot.as_mut_ptr(),
+                        energy.as_mut_ptr(),
+                        size,
+                    )
+                }
+            })?;
+            energy.resize(pixel_count, 0.0);
+            toa_itot.resize(pixel_count, 0.0);
+            return Ok(Readout::calibrated(energy, second(toa_itot)));
         }
-        Ok((min_voltage, max_voltage))
+
+        let mut tot_event = Vec::new();
+        measure_resizing(index, pixel_count, |len, size| {
+            tot_event.resize(len, 0);
+            toa_itot.resize(len, 0.0);
+            unsafe {
+                pxcGetMeasuredFrameTpx2(
+                    index,
+                    frame_index,
+                    toa_itot.as_mut_ptr(),
+                    tot_event.as_mut_ptr(),
+                    size,
+                )
+            }
+        })?;
+        tot_event.resize(pixel_count, 0);
+        toa_itot.resize(pixel_count, 0.0);
+        if mode == Tpx2Mode::Toa14 {
+            // the ToA does not fit into 16 bits, it is kept wide next to the image
+            return Ok(Readout {
+                image: toa_itot.iter().map(|&toa| toa as c_short).collect(),
+                second: None,
+                energy: None,
+                toa: Some(toa_itot),
+            });
+        }
+        Ok(Readout {
+            image: Self::to_buffer(tot_event),
+            second: second(toa_itot),
+            energy: None,
+            toa: None,
+        })
+    }
+
+    /// ToT or hit count converted to 16 bit samples
+    fn to_buffer(tot_event: Vec<c_ushort>) -> PxcBuffer {
+        tot_event
+            .into_iter()
+            .map(|tot| tot.min(c_short::MAX as c_ushort) as c_short)
+            .collect()
+    }
+}
+
+impl Device for Tpx2Device {
+    fn capture_frames(
+        &self,
+        count: usize,
+        on_frame: &mut dyn FnMut(Readout) -> ControlFlow<()>,
+    ) -> PxcResult<()> {
+        self.core
+            .measure_frames(count, self.frame_reader(), on_frame)
+    }
+    fn capture_readout(&self) -> PxcResult<Readout> {
+        self.core.measure_frame(self.frame_reader())
+    }
+
+    /// only the first channel, see `capture_dual_image`
+    fn capture_image(&self) -> PxcResult<PxcBuffer> {
+        Ok(self.capture_readout()?.image)
     }
-    fn set_high_voltage(&self, voltage: c_double) -> PxcResult<()> {
-        unsafe {
-            pxcSetBias(self.index, voltage).check_rc()?;
+
+    fn save_last_frame(&self, file_path: &str) -> PxcResult<()> {
+        self.core.save_last_frame(file_path)
+    }
+
+    pxcore_device_core!();
+
+    fn channels(&self) -> (Channel, Option<Channel>) {
+        match self.mode {
+            Tpx2Mode::Tot10Toa18 | Tpx2Mode::Tot14Toa14 => (Channel::Tot, Some(Channel::Toa)),
+            Tpx2Mode::Tot10Count4 => (Channel::Tot, Some(Channel::Count)),
+            Tpx2Mode::Tot14 => (Channel::Tot, None),
+            Tpx2Mode::Toa14 => (Channel::Toa, None),
+            Tpx2Mode::Count14 => (Channel::Count, None),
         }
+    }
+    /// ToT, and ToA or hit count
+    fn capture_dual_image(&self) -> PxcResult<(PxcBuffer, Vec<f64>)> {
+        if !self.is_dual_channel() {
+            return Err(PxcErr::NotSupported.into());
+        }
+        let readout = self.capture_readout()?;
+        Ok((readout.image, readout.second.unwrap_or_default()))
+    }
+    fn set_tpx2_mode(&mut self, mode: Tpx2Mode) -> PxcResult<()> {
+        self.core
+            .device
+            .check(|index| unsafe { pxcSetTimepix2Mode(index, mode as i32) })?;
+        self.mode = mode;
         Ok(())
     }
+    fn get_tpx2_mode(&self) -> Option<Tpx2Mode> {
+        Some(self.mode)
+    }
 
-    fn set_software_high_threshold(&mut self, high_threshold: f64) {
-        self.high_threshold = high_threshold;
+    /// energy of the ToT channel, the second channel is not calibrated
+    fn is_calibrated(&self) -> bool {
+        self.calibrated && self.channels().0 == Channel::Tot
     }
-    fn set_software_low_threshold(&mut self, low_threshold: f64) {
-        self.low_threshold = low_threshold;
+    fn capture_energy(&self) -> PxcResult<Vec<f64>> {
+        self.capture_readout()?
+            .energy
+            .ok_or_else(|| PxcErr::NotSupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUFFER_SMALL: c_int = -11;
+
+    #[test]
+    fn small_buffers_are_grown() {
+        let mut lens = Vec::new();
+        measure_resizing(0, 4, |len, size| {
+            lens.push(len);
+            match len < 6 {
+                true => {
+                    *size = 6;
+                    BUFFER_SMALL
+                }
+                false => 0,
+            }
+        })
+        .unwrap();
+        assert_eq!(lens, vec![4, 8]);
+
+        let always_small = measure_resizing(0, 4, |_, _| BUFFER_SMALL);
+        assert!(always_small.is_err_and(|why| why == PxcErr::BufferSmall));
+    }
+
+    #[test]
+    fn sleeping_can_be_aborted() {
+        let signal = AbortSignal::default();
+        // nothing to abort yet
+        signal.abort();
+        assert!(signal.sleep(Duration::from_millis(1)).is_ok());
+
+        let started = std::time::Instant::now();
+        std::thread::scope(|scope| {
+            let sleeper = scope.spawn(|| signal.sleep(Duration::from_secs(10)));
+            while !sleeper.is_finished() {
+                signal.abort();
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            assert!(
+                sleeper
+                    .join()
+                    .unwrap()
+                    .is_err_and(|why| why == PxcErr::AcqAborted)
+            );
+        });
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn settings_are_written_as_json() {
+        let settings = DeviceSettings {
+            frame_time: Seconds(0.5),
+            high_voltage: Some(Volts(40.0)),
+            threshold: None,
+            low_threshold: 2.5,
+            high_threshold: f64::INFINITY,
+            mode: TpxMode::Tot,
+            trigger: TriggerMode::SwStart,
+            second_threshold: None,
+            charge_summing: Some(true),
+            tpx2_mode: Some(Tpx2Mode::Tot10Count4),
+        };
+        assert_eq!(
+            settings.to_json(),
+            concat!(
+                r#"{"frame_time":0.5,"high_voltage":40,"threshold":null,"low_threshold":2.5,"#,
+                r#""high_threshold":null,"mode":"tot","trigger":"sw-start","second_threshold":null,"#,
+                r#""charge_summing":true,"tpx2_mode":"tot-count"}"#
+            )
+        );
+        for trigger in ["none", "hw-start", "hw-stop", "hw-start-stop", "sw-start"] {
+            assert_eq!(trigger.parse::<TriggerMode>().unwrap().to_string(), trigger);
+        }
+        for mode in ["tot-toa", "tot-toa-14", "tot-count", "tot", "toa", "count"] {
+            assert_eq!(mode.parse::<Tpx2Mode>().unwrap().to_string(), mode);
+        }
+    }
+
+    #[test]
+    fn triggers_start_waiting_measurements() {
+        let signal = AbortSignal::default();
+        assert!(!signal.is_armed());
+        assert!(signal.trigger().is_err_and(|why| why == PxcErr::NotAllowed));
+
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| signal.wait_for_trigger());
+            while !signal.is_armed() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            signal.trigger().unwrap();
+            assert!(waiter.join().unwrap().is_ok());
+            assert!(!signal.is_armed());
+
+            let waiter = scope.spawn(|| signal.wait_for_trigger());
+            while !signal.is_armed() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            signal.abort();
+            assert!(
+                waiter
+                    .join()
+                    .unwrap()
+                    .is_err_and(|why| why == PxcErr::AcqAborted)
+            );
+        });
     }
 }
@@ -1,26 +1,45 @@
 #![allow(dead_code)]
 
-use std::ffi::{c_char, c_double, c_int, c_uint};
+use crate::api::loader::Library;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CStr, c_char, c_double, c_int, c_uint, c_void};
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, mpsc};
+use std::thread::{self, JoinHandle};
 
 type CStringPointer = *const c_char;
 
-pub type PxcBuffer = [std::ffi::c_short; 65536];
+/// row major frame of `width * height` pixels
+pub type PxcBuffer = Vec<std::ffi::c_short>;
 
-pub type PxcResult<T> = Result<T, PxcErr>;
+/// called by pxcore during a measurement with `(event_data, user_data)`
+pub type AcqEventCallback = unsafe extern "C" fn(isize, isize);
+
+pub type PxcResult<T> = Result<T, PxcError>;
 /// helper trait to ignore PxcErr :3
 pub trait PxcIgnoreErr {
     fn ignore_error(self);
+    fn succeeded(self) -> bool;
 }
 impl<T> PxcIgnoreErr for PxcResult<T> {
     /// consumes the Result and prints the error if it encountered one
     fn ignore_error(self) {
         if let Err(ref err) = self {
-            eprintln!("[err]PxcErr caught: {:?}", err);
+            eprintln!("[err]PxcErr caught: {err}");
         }
     }
+    /// same as `ignore_error`, but returns whether there was no error
+    fn succeeded(self) -> bool {
+        let ok = self.is_ok();
+        self.ignore_error();
+        ok
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(i32)]
 pub enum PxcErr {
     NotInitialized = -1,
@@ -38,6 +57,12 @@ pub enum PxcErr {
     TooManyBadPixels = -13,
     ZestNotLoaded = -14,
     UnexpectedError(c_int) = -1000,
+    /// a replayed device ran out of frames
+    ReplayFinished = -1001,
+    /// the pxcore library could not be loaded
+    LibraryNotLoaded = -1002,
+    /// the loaded pxcore library does not export the called function
+    SymbolNotFound = -1003,
 }
 impl From<c_int> for PxcErr {
     fn from(val: c_int) -> Self {
@@ -56,29 +81,645 @@ impl From<c_int> for PxcErr {
             -12 => PxcErr::CannotCalibrate,
             -13 => PxcErr::TooManyBadPixels,
             -14 => PxcErr::ZestNotLoaded,
+            PXC_ERR_REPLAY_FINISHED => PxcErr::ReplayFinished,
+            PXC_ERR_LIBRARY_NOT_LOADED => PxcErr::LibraryNotLoaded,
+            PXC_ERR_SYMBOL_NOT_FOUND => PxcErr::SymbolNotFound,
             _ => PxcErr::UnexpectedError(val),
         }
     }
 }
 
+impl fmt::Display for PxcErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PxcErr::NotInitialized => write!(f, "pxcore is not initialized"),
+            PxcErr::InvalidDeviceIndex => write!(f, "invalid device index"),
+            PxcErr::InvalidArgument => write!(f, "invalid argument"),
+            PxcErr::CouldNotSave => write!(f, "could not save"),
+            PxcErr::AcqFailed => write!(f, "acquisition failed"),
+            PxcErr::DeviceError => write!(f, "device error"),
+            PxcErr::AcqAborted => write!(f, "acquisition aborted"),
+            PxcErr::CannotReconnect => write!(f, "cannot reconnect device"),
+            PxcErr::NotAllowed => write!(f, "not allowed"),
+            PxcErr::NotSupported => write!(f, "not supported"),
+            PxcErr::BufferSmall => write!(f, "buffer too small"),
+            PxcErr::CannotCalibrate => write!(f, "cannot calibrate"),
+            PxcErr::TooManyBadPixels => write!(f, "too many bad pixels"),
+            PxcErr::ZestNotLoaded => write!(f, "zest is not loaded"),
+            PxcErr::UnexpectedError(rc) => write!(f, "unexpected error code {rc}"),
+            PxcErr::ReplayFinished => write!(f, "replay finished"),
+            PxcErr::LibraryNotLoaded => write!(f, "pxcore could not be loaded"),
+            PxcErr::SymbolNotFound => write!(f, "function missing from pxcore"),
+        }
+    }
+}
+
+/// `PxcErr` together with where it happened and what pxcore said about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PxcError {
+    pub kind: PxcErr,
+    /// pxcore function which failed
+    pub operation: Option<&'static str>,
+    pub device: Option<c_uint>,
+    /// text of `pxcGetLastError`
+    pub message: Option<String>,
+}
+impl PxcError {
+    pub fn on_device(self, index: c_uint) -> Self {
+        Self {
+            device: Some(index),
+            ..self
+        }
+    }
+}
+impl From<PxcErr> for PxcError {
+    fn from(kind: PxcErr) -> Self {
+        Self {
+            kind,
+            operation: None,
+            device: None,
+            message: None,
+        }
+    }
+}
+impl PartialEq<PxcErr> for PxcError {
+    fn eq(&self, other: &PxcErr) -> bool {
+        self.kind == *other
+    }
+}
+impl fmt::Display for PxcError {
+    /// e.g. `pxcSetBias failed on device 0: invalid argument (Bias out of range)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.operation, self.device) {
+            (Some(operation), Some(index)) => write!(f, "{operation} failed on device {index}: ")?,
+            (Some(operation), None) => write!(f, "{operation} failed: ")?,
+            (None, Some(index)) => write!(f, "device {index}: ")?,
+            (None, None) => {}
+        }
+        write!(f, "{}", self.kind)?;
+        if let Some(message) = &self.message {
+            write!(f, " ({message})")?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for PxcError {}
+
+/// last failed pxcore call of this thread, picked up by `check_rc`
+struct Failure {
+    rc: c_int,
+    operation: &'static str,
+    message: Option<String>,
+}
+
+thread_local! {
+    static LAST_FAILURE: RefCell<Option<Failure>> = const { RefCell::new(None) };
+}
+
+fn record_failure(rc: c_int, operation: &'static str, message: Option<String>) {
+    LAST_FAILURE.with_borrow_mut(|failure| {
+        *failure = Some(Failure {
+            rc,
+            operation,
+            message,
+        })
+    });
+}
+
 /// helper trait to check the return code of FFI functions
 pub trait PxcErrCheck {
     fn check_rc(self) -> PxcResult<c_int>;
+    fn check_device(self, index: c_uint) -> PxcResult<c_int>;
 }
 impl PxcErrCheck for c_int {
     /// helper function to convert the return code of FFI functions into a `Result`
+    ///
+    /// the error names the failed function and carries the pxcore error message
     fn check_rc(self) -> PxcResult<c_int> {
+        let failure = LAST_FAILURE.with_borrow_mut(Option::take);
         if self < 0 {
-            return Err(PxcErr::from(self));
+            let mut error = PxcError::from(PxcErr::from(self));
+            if let Some(failure) = failure.filter(|failure| failure.rc == self) {
+                error.operation = Some(failure.operation);
+                error.message = failure.message;
+            }
+            return Err(error);
         }
         Ok(self)
     }
+    /// same as `check_rc` for functions called on a single device
+    fn check_device(self, index: c_uint) -> PxcResult<c_int> {
+        self.check_rc().map_err(|why| why.on_device(index))
+    }
+}
+
+#[cfg(windows)]
+const LIBRARY_NAME: &str = "pxcore.dll";
+#[cfg(target_os = "macos")]
+const LIBRARY_NAME: &str = "libpxcore.dylib";
+#[cfg(all(unix, not(target_os = "macos")))]
+const LIBRARY_NAME: &str = "libpxcore.so";
+
+/// Directories searched for pxcore, in order:
+/// - `PIXET_LIB_DIR` environment variable
+/// - directory of the executable
+/// - libraries vendored in this repo for the build target
+///
+/// if none of them contain the library, the system search path is used
+fn library_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = std::env::var_os("PIXET_LIB_DIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+    {
+        dirs.push(dir);
+    }
+    if let Some(dir) = option_env!("PIXET_VENDORED_LIB_DIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+fn load_library() -> Result<Library, String> {
+    let mut errors = Vec::new();
+    for dir in library_dirs() {
+        let path = dir.join(LIBRARY_NAME);
+        if !path.exists() {
+            continue;
+        }
+        // aarch64 pxcore expects zest to already be loaded
+        if cfg!(target_arch = "aarch64") {
+            let _ = Library::open(&dir.join("libzest.so"), true);
+        }
+        match Library::open(&path, false) {
+            Ok(library) => return Ok(library),
+            Err(why) => errors.push(why),
+        }
+    }
+    match Library::open(LIBRARY_NAME.as_ref(), false) {
+        Ok(library) => Ok(library),
+        Err(why) => {
+            errors.push(why);
+            Err(errors.join("; "))
+        }
+    }
+}
+
+/// Declares the pxcore functions
+///
+/// The functions are resolved when pxcore is first used instead of being linked,
+/// so the crate builds and runs without the library.
+/// A missing library returns `PxcErr::LibraryNotLoaded`, a missing function `PxcErr::SymbolNotFound`
+///
+/// They may only be called on the pxcore owner thread, see `PxcOwner`
+macro_rules! pxcore_functions {
+    ($(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> c_int;)*) => {
+        #[allow(non_snake_case)]
+        struct PxCore {
+            $($name: Option<unsafe extern "C" fn($($ty),*) -> c_int>,)*
+        }
+
+        impl PxCore {
+            /// whether the loaded library has the function `name`
+            fn exports(&self, name: &str) -> bool {
+                match name {
+                    $(stringify!($name) => self.$name.is_some(),)*
+                    _ => false,
+                }
+            }
+
+            fn load(library: &Library) -> Self {
+                Self {
+                    $($name: library
+                        .symbol(&std::ffi::CString::new(stringify!($name)).unwrap())
+                        .map(|symbol| unsafe {
+                            std::mem::transmute::<*mut c_void, unsafe extern "C" fn($($ty),*) -> c_int>(
+                                symbol,
+                            )
+                        }),)*
+                }
+            }
+        }
+
+        $(
+            #[allow(non_snake_case)]
+            pub unsafe fn $name($($arg: $ty),*) -> c_int {
+                let pxcore = match pxcore() {
+                    Ok(pxcore) => pxcore,
+                    Err(why) => {
+                        record_failure(PXC_ERR_LIBRARY_NOT_LOADED, stringify!($name), Some(why.clone()));
+                        return PXC_ERR_LIBRARY_NOT_LOADED;
+                    }
+                };
+                let Some(function) = pxcore.$name else {
+                    record_failure(PXC_ERR_SYMBOL_NOT_FOUND, stringify!($name), None);
+                    return PXC_ERR_SYMBOL_NOT_FOUND;
+                };
+                assert!(
+                    IS_OWNER.get(),
+                    "{} called outside of the pxcore owner thread",
+                    stringify!($name)
+                );
+                let rc = unsafe { function($($arg),*) };
+                // the error message has to be read right after the call
+                if rc < 0 {
+                    record_failure(rc, stringify!($name), last_error_message(pxcore));
+                }
+                rc
+            }
+        )*
+    };
+}
+
+const PXC_ERR_REPLAY_FINISHED: c_int = -1001;
+const PXC_ERR_LIBRARY_NOT_LOADED: c_int = -1002;
+const PXC_ERR_SYMBOL_NOT_FOUND: c_int = -1003;
+
+static PXCORE: OnceLock<Result<PxCore, String>> = OnceLock::new();
+
+/// loads pxcore on first use, the error says why it could not be loaded
+fn pxcore() -> Result<&'static PxCore, &'static String> {
+    PXCORE
+        .get_or_init(|| load_library().map(|library| PxCore::load(&library)))
+        .as_ref()
+}
+
+/// whether pxcore can be loaded and exports the function `name`
+///
+/// for functions only some pxcore versions have
+pub fn pxcore_exports(name: &str) -> bool {
+    pxcore().is_ok_and(|pxcore| pxcore.exports(name))
+}
+
+/// frames pxcore keeps of a continuous measurement, the owner reads them out long before they are overwritten
+const FRAME_BUFFER_SIZE: c_uint = 16;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// message to the pxcore owner thread
+enum Message {
+    /// pxcore calls, they wait for a running blocking measurement to end
+    Call(Job),
+    /// aborts, triggers and frame readouts, served during blocking measurements as well
+    Control(Job),
+}
+
+/// State of the thread which makes every pxcore call
+///
+/// pxcore is not known to be thread safe, so every call is sent to this one thread, see `PxcOwner`
+struct Owner {
+    /// calls which arrived during a blocking measurement
+    deferred: VecDeque<Job>,
+    /// continuous measurements by the key their callback gets
+    continuous: HashMap<isize, Continuous>,
+    next_key: isize,
+    exited: bool,
+}
+
+/// continuous measurement, read out by the owner frame by frame as pxcore reports them
+struct Continuous {
+    index: c_uint,
+    /// reads out the next frame, `Break` once the measurement is done
+    on_frame: Box<dyn FnMut() -> ControlFlow<()> + Send>,
+}
+
+/// the owner of the current `PixHandle`, only one can exist at a time
+static OWNER: Mutex<Option<Owner>> = Mutex::new(None);
+/// messages to the owner, locked apart from its state while the owner waits for them
+static QUEUE: Mutex<Option<mpsc::Receiver<Message>>> = Mutex::new(None);
+/// where `frame_measured` sends the frames pxcore reports, set while an owner runs
+static FRAME_TARGET: Mutex<Option<mpsc::Sender<Message>>> = Mutex::new(None);
+
+thread_local! {
+    /// whether this thread may call pxcore, see `in_callback`
+    static IS_OWNER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// runs `f` on the state of the owner, `None` if no owner runs
+fn with_owner<T>(f: impl FnOnce(&mut Owner) -> T) -> Option<T> {
+    OWNER.lock().unwrap().as_mut().map(f)
+}
+
+impl Owner {
+    /// the next job to run, `None` once the owner exited
+    fn next_job() -> Option<Job> {
+        let deferred = with_owner(|owner| match owner.exited {
+            true => None,
+            false => Some(owner.deferred.pop_front()),
+        })
+        .flatten()?;
+        if let Some(job) = deferred {
+            return Some(job);
+        }
+        match QUEUE.lock().unwrap().as_ref()?.recv() {
+            Ok(Message::Call(job) | Message::Control(job)) => Some(job),
+            Err(_) => None,
+        }
+    }
+
+    /// runs jobs until the owner exits
+    fn run() {
+        IS_OWNER.set(true);
+        while let Some(job) = Self::next_job() {
+            job();
+        }
+        *FRAME_TARGET.lock().unwrap() = None;
+        *QUEUE.lock().unwrap() = None;
+        *OWNER.lock().unwrap() = None;
+    }
+
+    /// serves the controls sent since the blocking measurement on the owner thread started
+    fn serve_controls() {
+        loop {
+            let message = match QUEUE.lock().unwrap().as_ref() {
+                Some(queue) => queue.try_recv(),
+                None => return,
+            };
+            let Ok(message) = message else {
+                return;
+            };
+            match message {
+                Message::Control(job) => job(),
+                Message::Call(job) => {
+                    with_owner(|owner| owner.deferred.push_back(job));
+                }
+            }
+        }
+    }
+
+    /// reads out the frame pxcore reported for the continuous measurement `key`
+    fn frame_measured(key: isize) {
+        // frames reported after the measurement ended are dropped
+        let Some(mut measurement) = with_owner(|owner| owner.continuous.remove(&key)).flatten()
+        else {
+            return;
+        };
+        match (measurement.on_frame)() {
+            ControlFlow::Continue(()) => {
+                with_owner(|owner| owner.continuous.insert(key, measurement));
+            }
+            // only the frame which just started is lost
+            ControlFlow::Break(()) => unsafe {
+                pxcAbortMeasurement(measurement.index)
+                    .check_device(measurement.index)
+                    .ignore_error();
+            },
+        }
+    }
+
+    /// aborts the measurement of the device, a continuous one ends with it
+    fn abort(index: c_uint) -> PxcResult<()> {
+        with_owner(|owner| {
+            owner
+                .continuous
+                .retain(|_, measurement| measurement.index != index)
+        });
+        unsafe { pxcAbortMeasurement(index) }.check_device(index)?;
+        Ok(())
+    }
 }
 
-#[link(name = "pxcore", kind = "dylib")]
-unsafe extern "C" {
+/// tells the owner pxcore measured the next frame of the continuous measurement `key`
+///
+/// called on a thread of pxcore, which runs the measurement next to the owner
+unsafe extern "C" fn frame_measured(_acq_count: isize, key: isize) {
+    if let Some(target) = FRAME_TARGET.lock().unwrap().as_ref() {
+        let _ = target.send(Message::Control(Box::new(move || {
+            Owner::frame_measured(key)
+        })));
+    }
+}
+
+/// runs `call` as part of the blocking measurement running on the owner thread
+///
+/// for the callbacks of that measurement, which read out on the thread pxcore calls them on
+/// while the owner waits for pxcore. Controls sent to the owner meanwhile are served first
+pub fn in_callback<T>(call: impl FnOnce() -> T) -> T {
+    let outer = IS_OWNER.replace(true);
+    Owner::serve_controls();
+    let result = call();
+    IS_OWNER.set(outer);
+    result
+}
+
+/// lets the raw pointers of a pxcore call cross over to the owner thread
+struct AssertSend<F>(F);
+unsafe impl<F> Send for AssertSend<F> {}
+impl<F: FnOnce() -> T, T> AssertSend<F> {
+    // takes `self` so closures capture the whole wrapper instead of its field
+    fn call(self) -> T {
+        (self.0)()
+    }
+}
+
+/// Cheap handle to the pxcore owner thread, every pxcore call is sent through one
+///
+/// the thread is started and ended by `PixHandle`
+#[derive(Clone)]
+pub struct PxcOwner {
+    messages: mpsc::Sender<Message>,
+}
+
+impl fmt::Debug for PxcOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PxcOwner")
+    }
+}
+
+impl PxcOwner {
+    /// starts the owner thread, `PxcErr::NotAllowed` while another one runs
+    pub fn spawn() -> PxcResult<(Self, JoinHandle<()>)> {
+        let (messages, queue) = mpsc::channel();
+        {
+            let mut owner = OWNER.lock().unwrap();
+            if owner.is_some() {
+                return Err(PxcError {
+                    message: Some(String::from("pxcore already has an owner")),
+                    ..PxcError::from(PxcErr::NotAllowed)
+                });
+            }
+            *owner = Some(Owner {
+                deferred: VecDeque::new(),
+                continuous: HashMap::new(),
+                next_key: 0,
+                exited: false,
+            });
+        }
+        *QUEUE.lock().unwrap() = Some(queue);
+        *FRAME_TARGET.lock().unwrap() = Some(messages.clone());
+        let thread = thread::Builder::new()
+            .name(String::from("pxcore"))
+            .spawn(Owner::run)
+            .expect("Failed to spawn pxcore thread");
+        Ok((Self { messages }, thread))
+    }
+
+    /// runs `call` on the owner thread and waits for its result
+    ///
+    /// waits for a blocking measurement running on the owner to end first.
+    /// `PxcErr::NotInitialized` once the owner exited
+    pub fn call<T: Send>(&self, call: impl FnOnce() -> PxcResult<T>) -> PxcResult<T> {
+        self.send(Message::Call, call)
+    }
+
+    /// same as `call`, but served during a blocking measurement as well
+    fn control<T: Send>(&self, control: impl FnOnce() -> PxcResult<T>) -> PxcResult<T> {
+        self.send(Message::Control, control)
+    }
+
+    fn send<T: Send>(
+        &self,
+        message: fn(Job) -> Message,
+        call: impl FnOnce() -> PxcResult<T>,
+    ) -> PxcResult<T> {
+        if IS_OWNER.get() {
+            return call();
+        }
+        let call = AssertSend(call);
+        let (result_tx, result_rx) = mpsc::sync_channel(1);
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+            let _ = result_tx.send(call.call());
+        });
+        // SAFETY: the job is either run or dropped before `recv` returns,
+        // so nothing it borrows can go out of scope while it is queued
+        let job: Job = unsafe { std::mem::transmute(job) };
+        let exited = || PxcError {
+            message: Some(String::from("the pxcore thread exited")),
+            ..PxcError::from(PxcErr::NotInitialized)
+        };
+        self.messages.send(message(job)).map_err(|_| exited())?;
+        result_rx.recv().map_err(|_| exited())?
+    }
+
+    /// ends the owner thread after the calls sent before, running `exit` as its last job
+    pub fn exit(&self, thread: JoinHandle<()>, exit: impl FnOnce() + Send) {
+        let _ = self.call(|| {
+            exit();
+            with_owner(|owner| owner.exited = true);
+            Ok(())
+        });
+        let _ = thread.join();
+    }
+}
+
+/// Cheap handle to a device of the pxcore owner, keyed on its pxcore index
+#[derive(Clone, Debug)]
+pub struct PxcDevice {
+    pub index: c_uint,
+    owner: PxcOwner,
+}
+
+impl PxcDevice {
+    pub fn new(owner: PxcOwner, index: c_uint) -> Self {
+        Self { index, owner }
+    }
+
+    /// runs `call` with the index of the device on the owner thread, see `PxcOwner::call`
+    ///
+    /// blocking measurements are a single call, their callbacks read out with `in_callback`
+    pub fn call<T: Send>(&self, call: impl FnOnce(c_uint) -> PxcResult<T>) -> PxcResult<T> {
+        self.owner.call(|| call(self.index))
+    }
+
+    /// runs the pxcore function `call` on the owner thread and checks its return code
+    pub fn check(&self, call: impl FnOnce(c_uint) -> c_int) -> PxcResult<c_int> {
+        self.call(|index| call(index).check_device(index))
+    }
+
+    /// aborts the measurement of the device, even while the owner waits for a blocking one
+    pub fn abort(&self) -> PxcResult<()> {
+        self.owner.control(|| Owner::abort(self.index))
+    }
+
+    /// whether the measurement of the device waits for `software_trigger`
+    pub fn is_ready_for_trigger(&self) -> PxcResult<bool> {
+        self.owner.control(|| {
+            let ready =
+                unsafe { pxcIsReadyForSoftwareTrigger(self.index) }.check_device(self.index)?;
+            Ok(ready > 0)
+        })
+    }
+
+    /// starts the measurement of the device waiting for a software trigger
+    pub fn software_trigger(&self) -> PxcResult<()> {
+        self.owner.control(|| {
+            unsafe { pxcDoSoftwareTrigger(self.index) }.check_device(self.index)?;
+            Ok(())
+        })
+    }
+
+    /// starts a continuous measurement of `count` frames, which leaves the owner free for other calls
+    ///
+    /// the owner reads out every frame with `read_frame` as soon as pxcore reports it,
+    /// `read_frame` gets the index of the frame in pxcore's buffer.
+    /// The readouts arrive on the returned channel, it ends after `count` frames, an error or `abort`
+    pub fn measure_continuous<T: Send + 'static>(
+        &self,
+        count: usize,
+        frame_time: c_double,
+        trigger: c_uint,
+        mut read_frame: impl FnMut(c_uint) -> PxcResult<T> + Send + 'static,
+    ) -> PxcResult<mpsc::Receiver<PxcResult<T>>> {
+        let (frames, receiver) = mpsc::channel();
+        let mut measured = 0;
+        let mut on_frame = Some(Box::new(move || {
+            let readout = read_frame((measured % FRAME_BUFFER_SIZE as usize) as c_uint);
+            measured += 1;
+            let failed = readout.is_err();
+            match frames.send(readout) {
+                Ok(()) if !failed && measured < count => ControlFlow::Continue(()),
+                // done, failed or the receiver is gone
+                _ => ControlFlow::Break(()),
+            }
+        }) as Box<dyn FnMut() -> ControlFlow<()> + Send>);
+        self.call(|index| {
+            let key = with_owner(|owner| {
+                let key = owner.next_key;
+                owner.next_key += 1;
+                let on_frame = on_frame.take().unwrap();
+                owner.continuous.insert(key, Continuous { index, on_frame });
+                key
+            })
+            .unwrap_or_default();
+            let result = unsafe {
+                pxcMeasureContinuous(
+                    index,
+                    FRAME_BUFFER_SIZE,
+                    frame_time,
+                    trigger,
+                    Some(frame_measured),
+                    key,
+                )
+            }
+            .check_device(index);
+            if result.is_err() {
+                with_owner(|owner| owner.continuous.remove(&key));
+            }
+            result
+        })?;
+        Ok(receiver)
+    }
+}
+
+/// text of the last error pxcore ran into, if it has one
+fn last_error_message(pxcore: &PxCore) -> Option<String> {
+    let get_last_error = pxcore.pxcGetLastError?;
+    let mut buffer: [c_char; 512] = [0; 512];
+    if unsafe { get_last_error(buffer.as_mut_ptr(), buffer.len() as c_uint) } < 0 {
+        return None;
+    }
+    let message = unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_string_lossy();
+    let message = message.trim();
+    (!message.is_empty()).then(|| message.to_owned())
+}
+
+pxcore_functions! {
     pub fn pxcSetDirectories(config_dir: CStringPointer, log_dir: CStringPointer) -> c_int;
     pub fn pxcInitialize(argc: c_int, argv: *const *const c_char) -> c_int;
+    pub fn pxcGetLastError(errorMsgBuffer: *mut c_char, size: c_uint) -> c_int;
 
     pub fn pxcGetDevicesCount() -> c_int;
     pub fn pxcRefreshDevices() -> c_int;
@@ -91,22 +732,83 @@ unsafe extern "C" {
 
     pub fn pxcGetBiasRange(index: c_uint, minBias: *mut c_double, maxBias: *mut c_double) -> c_int;
     pub fn pxcSetBias(index: c_uint, bias: c_double) -> c_int;
+    pub fn pxcGetBias(index: c_uint, bias: *mut c_double) -> c_int;
+    pub fn pxcGetDeviceParameterDouble(
+        index: c_uint,
+        parameterName: CStringPointer,
+        parameterValue: *mut c_double,
+    ) -> c_int;
     pub fn pxcSetThreshold(index: c_uint, thresholdIndex: c_int, threshold: c_double) -> c_int;
     pub fn pxcSetTimepixMode(index: c_uint, mode: c_int) -> c_int;
     pub fn pxcSetTimepixCalibrationEnabled(index: c_uint, enabled: bool) -> c_int;
     pub fn pxcIsTimepixCalibrationEnabled(index: c_uint) -> c_int;
 
     pub fn pxcGetMeasuredFrameCount(index: c_uint) -> c_int;
-    pub fn pxcMeasureSingleFrame(
+    pub fn pxcAbortMeasurement(index: c_uint) -> c_int;
+    pub fn pxcIsReadyForSoftwareTrigger(index: c_uint) -> c_int;
+    pub fn pxcDoSoftwareTrigger(index: c_uint) -> c_int;
+    pub fn pxcMeasureContinuous(
+        index: c_uint,
+        frameBufferSize: c_uint,
+        frameTime: c_double,
+        trgStg: c_uint,
+        callback: Option<AcqEventCallback>,
+        userData: isize,
+    ) -> c_int;
+    pub fn pxcMeasureSingleCalibratedFrame(
         index: c_uint,
-        frameTime: std::ffi::c_double,
-        frameData: &mut PxcBuffer,
+        frameTime: c_double,
+        frameData: *mut c_double,
         size: &mut c_uint,
+        trgStg: c_uint,
     ) -> c_int;
     pub fn pxcGetMeasuredFrame(
         index: c_uint,
         frameIndex: c_uint,
-        frameData: &mut PxcBuffer,
+        frameData: *mut std::ffi::c_short,
+        size: &mut c_uint,
+    ) -> c_int;
+
+    pub fn pxcSetTimepix3Mode(index: c_uint, mode: c_int) -> c_int;
+    pub fn pxcSetTimepix3CalibrationEnabled(index: c_uint, enabled: bool) -> c_int;
+    pub fn pxcMeasureTpx3DataDrivenMode(
+        index: c_uint,
+        measTime: c_double,
+        fileName: CStringPointer,
+        trgStg: c_uint,
+        callback: Option<AcqEventCallback>,
+        userData: isize,
+    ) -> c_int;
+    pub fn pxcGetMeasuredTpx3PixelsCount(index: c_uint, pixelCount: *mut c_uint) -> c_int;
+    pub fn pxcGetMeasuredTpx3Pixels(
+        index: c_uint,
+        pixels: *mut crate::api::device::Tpx3Pixel,
+        pixelCount: c_uint,
+    ) -> c_int;
+
+    pub fn pxcSetMedipix3OperationMode(index: c_uint, opMode: c_int) -> c_int;
+    pub fn pxcGetMeasuredFrameMpx3(
+        index: c_uint,
+        frameIndex: c_uint,
+        frameData1: *mut c_uint,
+        frameData2: *mut c_uint,
+        size: &mut c_uint,
+    ) -> c_int;
+
+    pub fn pxcSetTimepix2Mode(index: c_uint, mode: c_int) -> c_int;
+    pub fn pxcSetTimepix2CalibrationEnabled(index: c_uint, enabled: bool) -> c_int;
+    pub fn pxcGetMeasuredFrameTpx2(
+        index: c_uint,
+        frameIndex: c_uint,
+        frameToaITot: *mut c_double,
+        frameTotEvent: *mut std::ffi::c_ushort,
+        size: &mut c_uint,
+    ) -> c_int;
+    pub fn pxcGetMeasuredCalibratedFrameTpx2(
+        index: c_uint,
+        frameIndex: c_uint,
+        frameToaITot: *mut c_double,
+        frameTotEvent: *mut c_double,
         size: &mut c_uint,
     ) -> c_int;
 
@@ -118,3 +820,96 @@ unsafe extern "C" {
 
     pub fn pxcExit() -> c_int;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_carry_their_context() {
+        record_failure(-3, "pxcSetBias", Some(String::from("Bias out of range")));
+        let why = (-3).check_device(1).unwrap_err();
+        assert_eq!(why, PxcErr::InvalidArgument);
+        assert_eq!(
+            why.to_string(),
+            "pxcSetBias failed on device 1: invalid argument (Bias out of range)"
+        );
+
+        // the recorded failure belongs to the call before
+        record_failure(-3, "pxcSetBias", None);
+        assert_eq!(
+            (-5).check_rc().unwrap_err().to_string(),
+            "acquisition failed"
+        );
+        assert!(0.check_rc().is_ok());
+    }
+
+    /// owner of the tests, only one can run at a time
+    fn test_owner() -> &'static PxcOwner {
+        static TEST_OWNER: OnceLock<PxcOwner> = OnceLock::new();
+        TEST_OWNER.get_or_init(|| PxcOwner::spawn().unwrap().0)
+    }
+
+    #[test]
+    fn calls_run_on_the_owner_thread() {
+        let owner = test_owner();
+        let name = || Ok(thread::current().name().map(String::from));
+        assert_eq!(owner.call(name), Ok(Some(String::from("pxcore"))));
+        assert_eq!(
+            owner.call(|| owner.call(name)),
+            Ok(Some(String::from("pxcore")))
+        );
+        assert_eq!(PxcOwner::spawn().unwrap_err(), PxcErr::NotAllowed);
+
+        let mut size = 0;
+        owner
+            .call(|| {
+                size = 42;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(size, 42);
+    }
+
+    #[test]
+    fn controls_are_served_during_blocking_measurements() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::{Duration, Instant};
+
+        let owner = test_owner();
+        let measuring = AtomicBool::new(false);
+        let aborted = AtomicBool::new(false);
+        // blocks the owner, its callbacks run on another thread until it is aborted
+        let measure = || {
+            measuring.store(true, Ordering::SeqCst);
+            thread::scope(|scope| {
+                scope.spawn(|| {
+                    let started = Instant::now();
+                    while !aborted.load(Ordering::SeqCst)
+                        && started.elapsed() < Duration::from_secs(5)
+                    {
+                        in_callback(|| thread::sleep(Duration::from_millis(1)));
+                    }
+                });
+            });
+            Ok(aborted.load(Ordering::SeqCst))
+        };
+        thread::scope(|scope| {
+            let measurement = scope.spawn(|| owner.call(measure));
+            while !measuring.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            owner
+                .control(|| {
+                    aborted.store(true, Ordering::SeqCst);
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(
+                measurement.join().unwrap(),
+                Ok(true),
+                "abort was not served"
+            );
+        });
+    }
+}
@@ -1,154 +1,449 @@
 #![allow(dead_code)]
 
-use crate::api::device::{Device, TpxDevice};
+use crate::api::device::{
+    Device, DeviceCore, Mpx3Device, Tpx2Device, Tpx2Mode, Tpx3Device, Tpx3Mode, TpxDevice, TpxMode,
+    TriggerMode,
+};
 use crate::api::ffi::*;
+use crate::api::units::{KeV, Seconds, Volts};
 use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
 
-/// Only one PixHandle should ever exist
+/// whether a PixHandle currently exists
+static HANDLE_EXISTS: AtomicBool = AtomicBool::new(false);
+
+/// Directories pxcore reads its configuration from and writes its logs to
 ///
+/// default to the `PIXET_CONFIG_DIR` and `PIXET_LOG_DIR` environment variables,
+/// or `config` and `log` in the working directory if they are unset
+#[derive(Clone, Debug, PartialEq)]
+pub struct PixDirectories {
+    pub config: PathBuf,
+    pub log: PathBuf,
+}
+
+impl Default for PixDirectories {
+    fn default() -> Self {
+        let dir = |var, default| {
+            std::env::var_os(var)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(default))
+        };
+        Self {
+            config: dir("PIXET_CONFIG_DIR", "config"),
+            log: dir("PIXET_LOG_DIR", "log"),
+        }
+    }
+}
+
+/// Owns pxcore, every pxcore call is made by the owner thread the handle starts
+///
+/// Only one PixHandle can exist at a time, `new` fails while another one is alive.
 /// Cannot be a static, because those dont run Drop destructors
-pub struct PixHandle {}
+pub struct PixHandle {
+    owner: PxcOwner,
+    thread: Option<JoinHandle<()>>,
+}
 
 impl PixHandle {
-    pub fn new() -> Self {
-        unsafe {
-            let conf_dir = CString::new("config").unwrap();
-            let log_dir = CString::new("log").unwrap();
-            pxcSetDirectories(conf_dir.as_ptr(), log_dir.as_ptr());
-
-            pxcInitialize(0, std::ptr::null());
+    /// Starts the pxcore owner thread and initializes pxcore on it
+    pub fn new(directories: &PixDirectories) -> PxcResult<Self> {
+        if HANDLE_EXISTS.swap(true, Ordering::SeqCst) {
+            return Err(PxcError {
+                message: Some(String::from("only one PixHandle can exist")),
+                ..PxcError::from(PxcErr::NotAllowed)
+            });
         }
+        let (owner, thread) = match PxcOwner::spawn() {
+            Ok(owner) => owner,
+            Err(why) => {
+                HANDLE_EXISTS.store(false, Ordering::SeqCst);
+                return Err(why);
+            }
+        };
+        if let Err(why) = owner.call(|| Self::initialize(directories)) {
+            owner.exit(thread, || {});
+            HANDLE_EXISTS.store(false, Ordering::SeqCst);
+            return Err(why);
+        }
+
+        Ok(Self {
+            owner,
+            thread: Some(thread),
+        })
+    }
 
-        Self {}
+    fn initialize(directories: &PixDirectories) -> PxcResult<()> {
+        let conf_dir = path_to_cstring(&directories.config)?;
+        let log_dir = path_to_cstring(&directories.log)?;
+        unsafe {
+            pxcSetDirectories(conf_dir.as_ptr(), log_dir.as_ptr()).check_rc()?;
+            pxcInitialize(0, std::ptr::null()).check_rc()?;
+        }
+        Ok(())
     }
 }
 
-use crate::api::device::TpxMode;
+fn path_to_cstring(path: &Path) -> PxcResult<CString> {
+    CString::new(path.to_string_lossy().as_bytes()).map_err(|_| PxcError {
+        message: Some(format!("invalid directory {path:?}")),
+        ..PxcError::from(PxcErr::InvalidArgument)
+    })
+}
+
 impl PixHandle {
     /// Returns amount of currently connected devices
     ///
     /// [out] amount of devices
     pub fn get_device_count(&self) -> i32 {
-        unsafe { pxcGetDevicesCount() }
+        // only fails once the owner exited, like an uninitialized pxcore
+        self.owner
+            .call(|| Ok(unsafe { pxcGetDevicesCount() }))
+            .unwrap_or(-1)
     }
 
     /// Removes disconnected devices. Searches for new ones.
     ///
     /// [out] undefined return, most probably status
     pub fn refresh_devices(&self) -> i32 {
-        unsafe { pxcRefreshDevices() }
+        // only fails once the owner exited, like an uninitialized pxcore
+        self.owner
+            .call(|| Ok(unsafe { pxcRefreshDevices() }))
+            .unwrap_or(-1)
     }
 
     /// Builds device from a `DeviceBuilder`
-    pub fn get_device(&self, builder: DeviceBuilder) -> PxcResult<impl Device + use<>> {
-        match builder.info.r#type {
+    ///
+    /// returns the device along with the errors of the defaults and calibration it went without
+    pub fn get_device(
+        &self,
+        builder: DeviceBuilder,
+    ) -> PxcResult<(Box<dyn Device>, Vec<PxcError>)> {
+        let mut ignored = Vec::new();
+        let info = self.device_info(builder.index)?;
+        let frame_time = builder
+            .frame_time
+            .unwrap_or(FRAME_TIME_DEFAULT)
+            .check_frame_time()
+            .map_err(|why| why.on_device(builder.index))?;
+        let pxc_device = PxcDevice::new(self.owner.clone(), builder.index);
+        let core = DeviceCore::new(pxc_device.clone(), frame_time, info.dimensions);
+        let mut device: Box<dyn Device> = match info.device_type {
             DevType::Tpx => {
-                let mut width: std::ffi::c_uint = 0;
-                let mut height: std::ffi::c_uint = 0;
-                unsafe {
-                    pxcSetTimepixMode(builder.index, TpxMode::Tot as i32).check_rc()?;
-                    // attempt calibration (fails often)
-                    let calibrated = pxcIsTimepixCalibrationEnabled(builder.index).check_rc();
-                    if calibrated.is_err() {
-                        calibrated.ignore_error();
-                    } else {
-                        match calibrated.unwrap_or_else(|_e| {
-                            eprintln!("[err]how");
-                            0
-                        }) {
-                            0 => {
-                                println!("[info]Calibrating device");
-                                pxcSetTimepixCalibrationEnabled(builder.index, true)
-                                    .check_rc()
-                                    .ignore_error()
-                            }
-                            1.. => println!("[info]Device is calibrated"),
-                            _ => {}
-                        };
+                // not every pxcore can measure calibrated Timepix frames
+                let calibrated = match pxcore_exports("pxcMeasureSingleCalibratedFrame") {
+                    true => Self::enable_timepix_calibration(&pxc_device, &mut ignored),
+                    false => {
+                        println!(
+                            "[info]pxcore can not calibrate Timepix frames, measuring raw ToT"
+                        );
+                        false
                     }
-
-                    pxcGetDeviceDimensions(builder.index, &mut width, &mut height).check_rc()?;
+                };
+                let mut device = TpxDevice {
+                    core,
+                    mode: TpxMode::Tot,
+                    calibrated,
+                };
+                device.set_mode(builder.mode.unwrap_or_default())?;
+                Box::new(device)
+            }
+            DevType::Tpx3 => {
+                let calibrated = pxc_device
+                    .check(|index| unsafe { pxcSetTimepix3CalibrationEnabled(index, true) })
+                    .map_err(|why| ignored.push(why))
+                    .is_ok();
+                let mut device = Tpx3Device {
+                    core,
+                    mode: Tpx3Mode::ToaTot,
+                    calibrated,
+                };
+                device.set_mode(builder.mode.unwrap_or_default())?;
+                Box::new(device)
+            }
+            DevType::Mpx3 => {
+                let mut device = Mpx3Device {
+                    core,
+                    charge_summing: false,
+                    second_threshold: None,
+                };
+                apply_or_default(&mut ignored, builder.charge_summing, false, |enabled| {
+                    device.set_charge_summing(enabled)
+                })?;
+                if let Some(threshold) = builder.second_hardware_threshold {
+                    device.set_indexed_threshold(1, threshold)?;
                 }
-                let device = TpxDevice {
-                    index: builder.index,
-                    frame_time: builder.frame_time.unwrap_or(2.0),
-                    dimensions: (width, height),
-                    high_threshold: 0.0,
-                    low_threshold: 0.0,
+                Box::new(device)
+            }
+            DevType::Tpx2 => {
+                let calibrated = pxc_device
+                    .check(|index| unsafe { pxcSetTimepix2CalibrationEnabled(index, true) })
+                    .map_err(|why| ignored.push(why))
+                    .is_ok();
+                let mode = builder.tpx2_mode.unwrap_or(Tpx2Mode::Tot10Toa18);
+                let mut device = Tpx2Device {
+                    core,
+                    mode,
+                    calibrated,
                 };
+                device.set_tpx2_mode(mode)?;
+                Box::new(device)
+            }
+            DevType::Unknown(device_type) => {
+                return Err(PxcError {
+                    message: Some(format!("unknown device type {device_type}")),
+                    ..PxcError::from(PxcErr::NotSupported).on_device(builder.index)
+                });
+            }
+        };
+
+        apply_or_default(
+            &mut ignored,
+            builder.high_voltage,
+            builder.default_high_voltage.unwrap_or(HIGH_VOLTAGE_DEFAULT),
+            |voltage| device.set_high_voltage(voltage),
+        )?;
+        apply_or_default(
+            &mut ignored,
+            builder.hardware_threshold,
+            THRESHOLD_DEFAULT,
+            |threshold| device.set_threshold(threshold),
+        )?;
+        if let Some(trigger) = builder.trigger {
+            device.set_trigger(trigger)?;
+        }
+        Ok((device, ignored))
+    }
+
+    /// whether calibration is enabled, attempting to enable it (fails often)
+    fn enable_timepix_calibration(device: &PxcDevice, ignored: &mut Vec<PxcError>) -> bool {
+        match device.check(|index| unsafe { pxcIsTimepixCalibrationEnabled(index) }) {
+            Ok(0) => {
+                println!("[info]Calibrating device");
                 device
-                    .set_high_voltage(builder.high_voltage.unwrap_or(40.0))
-                    .ignore_error();
-                device
-                    .set_threshold(builder.hardware_threshold.unwrap_or(0.5))
-                    .ignore_error();
-                Ok(device)
+                    .check(|index| unsafe { pxcSetTimepixCalibrationEnabled(index, true) })
+                    .map_err(|why| ignored.push(why))
+                    .is_ok()
+            }
+            Ok(_) => {
+                println!("[info]Device is calibrated");
+                true
+            }
+            Err(why) => {
+                ignored.push(why);
+                false
+            }
+        }
+    }
+
+    /// Information about the connected device with the given serial number
+    ///
+    /// unlike the index, the serial number stays the same when devices are replugged
+    pub fn find_device(&self, serial: u32) -> PxcResult<DeviceInfo> {
+        self.list_devices()?
+            .into_iter()
+            .find(|info| info.serial == serial)
+            .ok_or_else(|| PxcError {
+                message: Some(format!("no device with serial number {serial}")),
+                ..PxcError::from(PxcErr::InvalidDeviceIndex)
+            })
+    }
+
+    /// Information about every connected device
+    pub fn list_devices(&self) -> PxcResult<Vec<DeviceInfo>> {
+        self.owner.call(|| {
+            let count = unsafe { pxcGetDevicesCount() }.check_rc()?;
+            (0..count as std::ffi::c_uint)
+                .map(DeviceInfo::read)
+                .collect()
+        })
+    }
+
+    /// Information about the device currently connected at `index`
+    pub fn device_info(&self, index: std::ffi::c_uint) -> PxcResult<DeviceInfo> {
+        self.owner.call(|| DeviceInfo::read(index))
+    }
+}
+
+/// applies a setting of the builder, only errors of explicitly set values are returned
+///
+/// errors of defaults are added to `ignored`
+fn apply_or_default<T>(
+    ignored: &mut Vec<PxcError>,
+    value: Option<T>,
+    default: T,
+    apply: impl FnOnce(T) -> PxcResult<()>,
+) -> PxcResult<()> {
+    match value {
+        Some(value) => apply(value),
+        None => {
+            if let Err(why) = apply(default) {
+                ignored.push(why);
             }
-            _ => unimplemented!(),
+            Ok(())
         }
     }
 }
 
 impl Drop for PixHandle {
+    /// exits pxcore once the calls sent before are done
     fn drop(&mut self) {
-        unsafe {
-            pxcExit();
+        if let Some(thread) = self.thread.take() {
+            self.owner.exit(thread, || unsafe {
+                pxcExit();
+            });
         }
+        HANDLE_EXISTS.store(false, Ordering::SeqCst);
     }
 }
 
+/// frame time of devices which were not given one
+pub const FRAME_TIME_DEFAULT: Seconds = Seconds(2.0);
+/// bias of devices which were not given one
+pub const HIGH_VOLTAGE_DEFAULT: Volts = Volts(40.0);
+/// hardware threshold of devices which were not given one
+pub const THRESHOLD_DEFAULT: KeV = KeV(0.5);
+
 #[derive(Default)]
 pub struct DeviceBuilder {
     index: std::ffi::c_uint,
-    info: CDevInfo,
-    frame_time: Option<std::ffi::c_double>,
-    high_voltage: Option<std::ffi::c_double>,
-    hardware_threshold: Option<std::ffi::c_double>,
+    frame_time: Option<Seconds>,
+    high_voltage: Option<Volts>,
+    /// bias used instead of `HIGH_VOLTAGE_DEFAULT` if no `high_voltage` is given
+    default_high_voltage: Option<Volts>,
+    hardware_threshold: Option<KeV>,
+    second_hardware_threshold: Option<KeV>,
+    charge_summing: Option<bool>,
+    tpx2_mode: Option<Tpx2Mode>,
+    mode: Option<TpxMode>,
+    trigger: Option<TriggerMode>,
 }
 
 impl DeviceBuilder {
     pub fn new(index: u32) -> Self {
-        let mut info = CDevInfo::default();
-        unsafe {
-            pxcGetDeviceInfo(index, &mut info);
-        }
-
         Self {
             index,
-            info,
             ..DeviceBuilder::default()
         }
     }
 
-    /// time to capture a frame for in seconds
+    /// time to capture a frame for, between 10µs and an hour
     ///
     /// defaults to 2s if unset
-    pub fn frame_time(mut self, seconds: f64) -> Self {
+    pub fn frame_time(mut self, seconds: Seconds) -> Self {
         self.frame_time = Some(seconds);
         self
     }
     /// voltage with which to collect the charges from the pixels
-    /// - `voltage`: safe values are 5V - 100V
+    /// - `voltage`: has to lie within the bias range pxcore reports for the sensor
     ///
     /// defaults to 40V if not set
-    pub fn high_voltage(mut self, voltage: f64) -> Self {
+    pub fn high_voltage(mut self, voltage: Volts) -> Self {
         self.high_voltage = Some(voltage);
         self
     }
-    /// values 100 - 500
+    /// bias to use if `high_voltage` is not set, failing to apply it does not fail the device
+    ///
+    /// defaults to 40V if not set
+    pub fn default_high_voltage(mut self, voltage: Volts) -> Self {
+        self.default_high_voltage = Some(voltage);
+        self
+    }
+    /// energy a hit has to deposit to be registered
     ///
-    /// defaults to 200 if not set
-    pub fn hardware_threshold(mut self, threshold: f64) -> Self {
+    /// defaults to 0.5keV if not set
+    pub fn hardware_threshold(mut self, threshold: KeV) -> Self {
         self.hardware_threshold = Some(threshold);
         self
     }
+    /// threshold of the second counter, only used by Medipix3
+    ///
+    /// left at the device default if not set
+    pub fn second_hardware_threshold(mut self, threshold: KeV) -> Self {
+        self.second_hardware_threshold = Some(threshold);
+        self
+    }
+    /// whether Medipix3 should sum up the charge of neighbouring pixels
+    ///
+    /// defaults to single pixel mode if not set
+    pub fn charge_summing(mut self, enabled: bool) -> Self {
+        self.charge_summing = Some(enabled);
+        self
+    }
+    /// whether Timepix and Timepix3 count hits, measure energy or time of arrival
+    ///
+    /// defaults to energy (ToT) if not set
+    pub fn mode(mut self, mode: TpxMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+    /// which quantities Timepix2 measures
+    ///
+    /// defaults to simultaneous ToT and ToA if not set
+    pub fn tpx2_mode(mut self, mode: Tpx2Mode) -> Self {
+        self.tpx2_mode = Some(mode);
+        self
+    }
+    /// what starts the measurements, `TriggerMode::HwStart` waits for the trigger input
+    /// so several devices wired to the same trigger start together
+    ///
+    /// measurements start right away if not set
+    pub fn trigger(mut self, trigger: TriggerMode) -> Self {
+        self.trigger = Some(trigger);
+        self
+    }
+}
+
+/// Validated information about a connected device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub index: std::ffi::c_uint,
+    pub name: String,
+    pub serial: u32,
+    pub device_type: DevType,
+    pub dimensions: (std::ffi::c_uint, std::ffi::c_uint),
+}
+
+impl DeviceInfo {
+    /// only on the pxcore owner thread, see `PixHandle::device_info`
+    fn read(index: std::ffi::c_uint) -> PxcResult<Self> {
+        let mut info = CDevInfo::default();
+        let mut width: std::ffi::c_uint = 0;
+        let mut height: std::ffi::c_uint = 0;
+        unsafe {
+            pxcGetDeviceInfo(index, &mut info).check_device(index)?;
+            pxcGetDeviceDimensions(index, &mut width, &mut height).check_device(index)?;
+        }
+
+        Ok(Self {
+            index,
+            name: read_name(&info.name),
+            serial: info.serial,
+            device_type: DevType::from(info.r#type),
+            dimensions: (width, height),
+        })
+    }
+}
+
+/// the name is not guaranteed to be terminated
+fn read_name(name: &[std::ffi::c_char]) -> String {
+    let name: Vec<u8> = name
+        .iter()
+        .map(|&char| char as u8)
+        .take_while(|&char| char != 0)
+        .collect();
+    String::from_utf8_lossy(&name).into_owned()
 }
 
+/// `pxcGetDeviceInfo` output, the type is kept as a plain int
+/// as pxcore may report types this crate does not know
 #[repr(C, packed)]
 pub struct CDevInfo {
     name: [std::ffi::c_char; 20],
     serial: u32,
-    r#type: DevType,
+    r#type: std::ffi::c_int,
 }
 
 impl Default for CDevInfo {
@@ -156,15 +451,45 @@ impl Default for CDevInfo {
         Self {
             name: [0 as std::ffi::c_char; 20],
             serial: 0,
-            r#type: DevType::Tpx,
+            r#type: 0,
         }
     }
 }
 
-#[repr(C)]
-enum DevType {
-    Tpx = 1,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevType {
+    Tpx,
     Mpx3,
     Tpx3,
     Tpx2,
+    Unknown(std::ffi::c_int),
+}
+
+impl From<std::ffi::c_int> for DevType {
+    fn from(val: std::ffi::c_int) -> Self {
+        match val {
+            1 => DevType::Tpx,
+            2 => DevType::Mpx3,
+            3 => DevType::Tpx3,
+            4 => DevType::Tpx2,
+            _ => DevType::Unknown(val),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_info_is_validated() {
+        assert_eq!(DevType::from(3), DevType::Tpx3);
+        assert_eq!(DevType::from(42), DevType::Unknown(42));
+
+        let name = b"MiniPIX".map(|char| char as std::ffi::c_char);
+        assert_eq!(read_name(&name), "MiniPIX");
+        let mut name = [0 as std::ffi::c_char; 20];
+        name[..3].copy_from_slice(&b"Tpx".map(|char| char as std::ffi::c_char));
+        assert_eq!(read_name(&name), "Tpx");
+    }
 }
@@ -0,0 +1,96 @@
+//! Minimal cross platform wrapper around `dlopen` / `LoadLibrary`
+
+use std::ffi::{CStr, CString, c_void};
+use std::path::Path;
+
+/// Handle to a loaded shared library
+///
+/// Libraries are never unloaded, symbols stay valid until the process exits
+pub struct Library(*mut c_void);
+
+// the handle is only ever used to look up symbols, which is thread safe on every platform
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+impl Library {
+    /// loads a shared library, returning the reason of the failure otherwise
+    pub fn open(path: &Path, global: bool) -> Result<Self, String> {
+        let c_path = CString::new(path.to_string_lossy().as_bytes())
+            .map_err(|_| format!("Invalid library path {path:?}"))?;
+        let handle = unsafe { sys::open(&c_path, global) };
+        if handle.is_null() {
+            return Err(format!("{}: {}", path.display(), sys::last_error()));
+        }
+        Ok(Self(handle))
+    }
+
+    /// address of an exported symbol, `None` if the library does not export it
+    pub fn symbol(&self, name: &CStr) -> Option<*mut c_void> {
+        let symbol = unsafe { sys::symbol(self.0, name) };
+        (!symbol.is_null()).then_some(symbol)
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{CStr, c_char, c_int, c_void};
+
+    const RTLD_NOW: c_int = 2;
+    #[cfg(target_os = "macos")]
+    const RTLD_GLOBAL: c_int = 8;
+    #[cfg(not(target_os = "macos"))]
+    const RTLD_GLOBAL: c_int = 0x100;
+
+    #[cfg_attr(target_os = "linux", link(name = "dl"))]
+    unsafe extern "C" {
+        fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *const c_char;
+    }
+
+    pub unsafe fn open(path: &CStr, global: bool) -> *mut c_void {
+        let flags = match global {
+            true => RTLD_NOW | RTLD_GLOBAL,
+            false => RTLD_NOW,
+        };
+        unsafe { dlopen(path.as_ptr(), flags) }
+    }
+
+    pub unsafe fn symbol(handle: *mut c_void, name: &CStr) -> *mut c_void {
+        unsafe { dlsym(handle, name.as_ptr()) }
+    }
+
+    pub fn last_error() -> String {
+        unsafe {
+            let error = dlerror();
+            if error.is_null() {
+                return String::from("unknown error");
+            }
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::{CStr, c_char, c_void};
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn LoadLibraryA(filename: *const c_char) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+        fn GetLastError() -> u32;
+    }
+
+    pub unsafe fn open(path: &CStr, _global: bool) -> *mut c_void {
+        unsafe { LoadLibraryA(path.as_ptr()) }
+    }
+
+    pub unsafe fn symbol(handle: *mut c_void, name: &CStr) -> *mut c_void {
+        unsafe { GetProcAddress(handle, name.as_ptr()) }
+    }
+
+    pub fn last_error() -> String {
+        format!("error code {}", unsafe { GetLastError() })
+    }
+}
@@ -1,3 +1,7 @@
 pub mod device;
 pub mod ffi;
 pub mod handle;
+pub mod loader;
+pub mod replay;
+pub mod sim;
+pub mod units;
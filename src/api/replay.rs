@@ -0,0 +1,278 @@
+#![allow(dead_code)]
+
+use crate::api::device::{AbortSignal, Device};
+use crate::api::ffi::{PxcBuffer, PxcErr, PxcError, PxcResult};
+use crate::api::handle::FRAME_TIME_DEFAULT;
+use crate::api::units::{KeV, Seconds, Volts};
+use crate::data_worker::threshold::Thresholds;
+use std::ffi::{c_double, c_uint};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// line between two frames saved in the raw rak matrix format
+const RAK_SEPARATOR: &str = "----------";
+/// starts the line with the device settings written at the beginning of every saved run
+pub const SETTINGS_PREFIX: &str = "[settings]";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayPacing {
+    /// hands out one frame per frame time, like the device that recorded them
+    Realtime,
+    /// hands out frames as fast as they can be read
+    Fast,
+}
+
+/// Device which plays back frames saved by `save_frame`
+///
+/// Both the almost json and the raw rak matrix formats are supported,
+/// the format is detected per frame.
+/// The frame time defaults to the one in the `[settings]` line of the file
+pub struct ReplayDevice {
+    pub frame_time: c_double,
+    pub pacing: ReplayPacing,
+    pub thresholds: Thresholds,
+    dimensions: (c_uint, c_uint),
+    state: Mutex<ReplayState>,
+    abort_signal: AbortSignal,
+}
+
+struct ReplayState {
+    lines: Lines<BufReader<File>>,
+    /// frame which was read ahead to find out the dimensions
+    pending: Option<Vec<Vec<i16>>>,
+    last_capture: Option<Instant>,
+}
+
+impl ReplayDevice {
+    pub fn open(path: impl AsRef<Path>, pacing: ReplayPacing) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let mut recorded_frame_time = None;
+        let first = read_frame(&mut lines, &mut recorded_frame_time)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "No frames in replay file")
+        })?;
+        let dimensions = (first[0].len() as c_uint, first.len() as c_uint);
+
+        Ok(Self {
+            frame_time: recorded_frame_time.unwrap_or(FRAME_TIME_DEFAULT).0,
+            pacing,
+            thresholds: Thresholds::default(),
+            dimensions,
+            state: Mutex::new(ReplayState {
+                lines,
+                pending: Some(first),
+                last_capture: None,
+            }),
+            abort_signal: AbortSignal::default(),
+        })
+    }
+}
+
+impl Device for ReplayDevice {
+    /// returns `PxcErr::ReplayFinished` once every frame was played back
+    ///
+    /// a frame which can not be read or has different dimensions returns `PxcErr::AcqFailed`,
+    /// the next capture continues with the frame after it
+    fn capture_image(&self) -> PxcResult<PxcBuffer> {
+        let mut state = self.state.lock().unwrap();
+
+        let rows = match state.pending.take() {
+            Some(rows) => rows,
+            // only the frame time of the first run is replayed
+            None => match read_frame(&mut state.lines, &mut None) {
+                Ok(Some(rows)) => rows,
+                Ok(None) => return Err(PxcErr::ReplayFinished.into()),
+                Err(why) => return Err(skipped(format!("failed to read replayed frame: {why}"))),
+            },
+        };
+        if (rows[0].len() as c_uint, rows.len() as c_uint) != self.dimensions {
+            return Err(skipped(String::from(
+                "skipped replayed frame with different dimensions",
+            )));
+        }
+
+        if self.pacing == ReplayPacing::Realtime {
+            let frame_time = self.get_frame_time().as_duration();
+            if let Some(elapsed) = state.last_capture.map(|last| last.elapsed()) {
+                self.abort_signal
+                    .sleep(frame_time.saturating_sub(elapsed))?;
+            }
+            state.last_capture = Some(Instant::now());
+        }
+
+        let mut data_buf: PxcBuffer = rows.into_iter().flatten().collect();
+        data_buf.resize(self.dimensions.0 as usize * self.dimensions.1 as usize, 0);
+        Ok(data_buf)
+    }
+
+    fn save_last_frame(&self, _file_path: &str) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+
+    fn get_dimensions(&self) -> (c_uint, c_uint) {
+        self.dimensions
+    }
+
+    fn abort(&self) -> PxcResult<()> {
+        self.abort_signal.abort();
+        Ok(())
+    }
+
+    fn get_voltage_range(&self) -> PxcResult<(Volts, Volts)> {
+        Err(PxcErr::NotSupported.into())
+    }
+    fn set_high_voltage(&mut self, _voltage: Volts) -> PxcResult<()> {
+        Ok(())
+    }
+    /// there is no sensor
+    fn get_high_voltage(&self) -> Option<Volts> {
+        None
+    }
+    fn set_threshold(&mut self, _threshold: KeV) -> PxcResult<()> {
+        Ok(())
+    }
+    fn get_threshold(&self) -> Option<KeV> {
+        None
+    }
+    fn set_frame_time(&mut self, seconds: Seconds) -> PxcResult<()> {
+        self.frame_time = seconds.check_frame_time()?.0;
+        Ok(())
+    }
+    fn get_frame_time(&self) -> Seconds {
+        Seconds(self.frame_time)
+    }
+
+    fn thresholds(&self) -> &Thresholds {
+        &self.thresholds
+    }
+    fn thresholds_mut(&mut self) -> &mut Thresholds {
+        &mut self.thresholds
+    }
+}
+
+fn skipped(message: String) -> PxcError {
+    PxcError {
+        message: Some(message),
+        ..PxcError::from(PxcErr::AcqFailed)
+    }
+}
+
+/// reads the next frame, `None` once there are no more frames
+///
+/// `frame_time` is set to the frame time of a `[settings]` line in front of it
+fn read_frame(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    frame_time: &mut Option<Seconds>,
+) -> io::Result<Option<Vec<Vec<i16>>>> {
+    let mut rows = Vec::new();
+    for line in lines {
+        let line = line?;
+        let line = line.trim();
+        if line.starts_with("Frame {") {
+            return parse_debug_frame(line).map(Some);
+        }
+        if line == RAK_SEPARATOR {
+            if rows.is_empty() {
+                continue;
+            }
+            return Ok(Some(rows));
+        }
+        if let Some(settings) = line.strip_prefix(SETTINGS_PREFIX) {
+            *frame_time = recorded_frame_time(settings).or(*frame_time);
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        rows.push(parse_row(line.split_whitespace())?);
+    }
+
+    match rows.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(rows)),
+    }
+}
+
+/// the `frame_time` of settings written by `DeviceSettings::to_json`, if it is a valid one
+fn recorded_frame_time(settings: &str) -> Option<Seconds> {
+    let key = "\"frame_time\":";
+    let start = settings.find(key)? + key.len();
+    let value = settings[start..].split([',', '}']).next()?;
+    let frame_time = Seconds(value.trim().parse().ok()?);
+    frame_time.check_frame_time().ok()
+}
+
+/// parses the `data` field out of a `Frame` formatted with `{:?}`
+fn parse_debug_frame(line: &str) -> io::Result<Vec<Vec<i16>>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Missing frame data");
+    let start = line.find("data: [[").ok_or_else(invalid)? + "data: [[".len();
+    let end = start + line[start..].find("]]").ok_or_else(invalid)?;
+
+    line[start..end]
+        .split("], [")
+        .map(|row| parse_row(row.split(", ")))
+        .collect()
+}
+
+fn parse_row<'a>(values: impl Iterator<Item = &'a str>) -> io::Result<Vec<i16>> {
+    values
+        .map(|val| {
+            val.parse::<i16>()
+                .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> impl Iterator<Item = io::Result<String>> {
+        text.lines()
+            .map(|line| Ok(line.to_owned()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn reading_both_save_modes_works() {
+        let mut frame_time = None;
+        let mut json = lines(concat!(
+            "[settings]{\"frame_time\":0.5,\"high_voltage\":null}\n",
+            "Frame { data: [[0, 1], [-2, 3]], particles: [] }\n",
+            "Frame { data: [[4, 5], [6, 7]], particles: [Particle { particle_type: Unknown, positions: [(0, 0, 4)] }] }\n",
+        ));
+        assert_eq!(
+            read_frame(&mut json, &mut frame_time).unwrap(),
+            Some(vec![vec![0, 1], vec![-2, 3]])
+        );
+        assert_eq!(
+            read_frame(&mut json, &mut frame_time).unwrap(),
+            Some(vec![vec![4, 5], vec![6, 7]])
+        );
+        assert_eq!(read_frame(&mut json, &mut frame_time).unwrap(), None);
+        assert_eq!(frame_time, Some(Seconds(0.5)));
+
+        let mut rak = lines("[settings]{}\n0 1 2\n3 4 5\n----------\n6 7 8\n----------\n");
+        assert_eq!(
+            read_frame(&mut rak, &mut frame_time).unwrap(),
+            Some(vec![vec![0, 1, 2], vec![3, 4, 5]])
+        );
+        assert_eq!(
+            read_frame(&mut rak, &mut frame_time).unwrap(),
+            Some(vec![vec![6, 7, 8]])
+        );
+        assert_eq!(read_frame(&mut rak, &mut frame_time).unwrap(), None);
+        // settings without a valid frame time keep the one read before
+        assert_eq!(frame_time, Some(Seconds(0.5)));
+        assert_eq!(
+            recorded_frame_time(r#"{"high_voltage":40,"frame_time":2}"#),
+            Some(Seconds(2.0))
+        );
+        assert_eq!(recorded_frame_time(r#"{"frame_time":-1}"#), None);
+
+        assert!(read_frame(&mut lines("0 x 2\n"), &mut frame_time).is_err());
+    }
+}
@@ -0,0 +1,445 @@
+#![allow(dead_code)]
+
+use crate::api::device::{AbortSignal, Channel, Device, TpxMode, TriggerMode};
+use crate::api::ffi::{PxcBuffer, PxcErr, PxcResult};
+use crate::api::handle::{FRAME_TIME_DEFAULT, HIGH_VOLTAGE_DEFAULT, THRESHOLD_DEFAULT};
+use crate::api::units::{KeV, Seconds, Volts};
+use crate::data_worker::threshold::Thresholds;
+use std::ffi::{c_double, c_short, c_uint};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIM_WIDTH: usize = 256;
+const SIM_HEIGHT: usize = 256;
+/// reported sensor temperature in °C
+const SIM_TEMPERATURE: f64 = 35.0;
+const SIM_VOLTAGE_RANGE: (Volts, Volts) = (Volts(5.0), Volts(100.0));
+/// latest time of arrival, in clock ticks
+const SIM_MAX_TOA: f64 = 11810.0;
+
+/// Expected amount of events per second for every kind of synthetic particle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimRates {
+    /// long straight tracks crossing a large part of the sensor
+    pub muon: f64,
+    /// round heavy blobs
+    pub alpha: f64,
+    /// small dots of 1 - 4 pixels
+    pub gamma: f64,
+    /// lone low energy pixels
+    pub noise: f64,
+}
+
+impl Default for SimRates {
+    fn default() -> Self {
+        Self {
+            muon: 0.2,
+            alpha: 0.05,
+            gamma: 5.0,
+            noise: 50.0,
+        }
+    }
+}
+
+impl FromStr for SimRates {
+    type Err = String;
+
+    /// parses rates in the form of `muon=0.5,alpha=0.1`
+    ///
+    /// unspecified rates keep their default value
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rates = SimRates::default();
+        for pair in s.split(",").filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair
+                .split_once("=")
+                .ok_or_else(|| format!("Missing '=' in rate '{pair}'"))?;
+            let value = value
+                .parse::<f64>()
+                .map_err(|why| format!("Invalid rate '{value}': {why}"))?;
+            if value < 0.0 {
+                return Err(format!("Negative rate '{value}'"));
+            }
+            match name {
+                "muon" => rates.muon = value,
+                "alpha" => rates.alpha = value,
+                "gamma" => rates.gamma = value,
+                "noise" => rates.noise = value,
+                _ => return Err(format!("Unknown particle '{name}'")),
+            }
+        }
+        Ok(rates)
+    }
+}
+
+/// Device which does not need any hardware
+///
+/// Generates synthetic frames with particles at the configured rates
+pub struct SimDevice {
+    pub frame_time: c_double,
+    pub rates: SimRates,
+    pub high_voltage: c_double,
+    pub threshold: c_double,
+    pub thresholds: Thresholds,
+    pub mode: TpxMode,
+    /// only the software trigger, there is no trigger input
+    pub trigger: TriggerMode,
+    /// whether `capture_image` should sleep for the frame time like a real device
+    pub realtime: bool,
+    rng: Mutex<Rng>,
+    abort_signal: AbortSignal,
+}
+
+impl SimDevice {
+    pub fn new(rates: SimRates) -> Self {
+        Self {
+            frame_time: FRAME_TIME_DEFAULT.0,
+            rates,
+            high_voltage: HIGH_VOLTAGE_DEFAULT.0,
+            threshold: THRESHOLD_DEFAULT.0,
+            thresholds: Thresholds::default(),
+            mode: TpxMode::Tot,
+            trigger: TriggerMode::None,
+            realtime: true,
+            rng: Mutex::new(Rng::from_time()),
+            abort_signal: AbortSignal::default(),
+        }
+    }
+
+    /// makes the generated frames reproducible
+    pub fn seed(self, seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(Rng::new(seed)),
+            ..self
+        }
+    }
+
+    /// waits for the trigger and the frame time like a real device
+    fn measure(&self) -> PxcResult<()> {
+        if self.trigger == TriggerMode::SwStart {
+            self.abort_signal.wait_for_trigger()?;
+        }
+        if self.realtime {
+            self.abort_signal
+                .sleep(self.get_frame_time().as_duration())?;
+        }
+        Ok(())
+    }
+
+    fn generate(&self, rng: &mut Rng) -> PxcBuffer {
+        let mut buf: PxcBuffer = vec![0; SIM_WIDTH * SIM_HEIGHT];
+        let mut canvas = Canvas {
+            buf: &mut buf,
+            mode: self.mode,
+            toa: 0.0,
+        };
+
+        for _ in 0..rng.poisson(self.rates.muon * self.frame_time) {
+            canvas.toa = rng.range(1.0, SIM_MAX_TOA);
+            draw_muon(&mut canvas, rng);
+        }
+        for _ in 0..rng.poisson(self.rates.alpha * self.frame_time) {
+            canvas.toa = rng.range(1.0, SIM_MAX_TOA);
+            draw_alpha(&mut canvas, rng);
+        }
+        for _ in 0..rng.poisson(self.rates.gamma * self.frame_time) {
+            canvas.toa = rng.range(1.0, SIM_MAX_TOA);
+            draw_gamma(&mut canvas, rng);
+        }
+        for _ in 0..rng.poisson(self.rates.noise * self.frame_time) {
+            canvas.toa = rng.range(1.0, SIM_MAX_TOA);
+            let x = rng.below(SIM_WIDTH);
+            let y = rng.below(SIM_HEIGHT);
+            canvas.deposit(x as isize, y as isize, rng.range(1.0, 10.0));
+        }
+        buf
+    }
+}
+
+impl Device for SimDevice {
+    fn capture_image(&self) -> PxcResult<PxcBuffer> {
+        self.measure()?;
+        let mut rng = self.rng.lock().unwrap();
+        Ok(self.generate(&mut rng))
+    }
+
+    fn save_last_frame(&self, _file_path: &str) -> PxcResult<()> {
+        Err(PxcErr::NotSupported.into())
+    }
+
+    fn get_dimensions(&self) -> (c_uint, c_uint) {
+        (SIM_WIDTH as c_uint, SIM_HEIGHT as c_uint)
+    }
+
+    fn get_voltage_range(&self) -> PxcResult<(Volts, Volts)> {
+        Ok(SIM_VOLTAGE_RANGE)
+    }
+    fn set_high_voltage(&mut self, voltage: Volts) -> PxcResult<()> {
+        self.high_voltage = voltage.check_range(SIM_VOLTAGE_RANGE)?.0;
+        Ok(())
+    }
+    fn get_high_voltage(&self) -> Option<Volts> {
+        Some(Volts(self.high_voltage))
+    }
+    fn set_threshold(&mut self, threshold: KeV) -> PxcResult<()> {
+        self.threshold = threshold.0;
+        Ok(())
+    }
+    fn get_threshold(&self) -> Option<KeV> {
+        Some(KeV(self.threshold))
+    }
+    fn set_frame_time(&mut self, seconds: Seconds) -> PxcResult<()> {
+        self.frame_time = seconds.check_frame_time()?.0;
+        Ok(())
+    }
+    fn get_frame_time(&self) -> Seconds {
+        Seconds(self.frame_time)
+    }
+
+    fn thresholds(&self) -> &Thresholds {
+        &self.thresholds
+    }
+    fn thresholds_mut(&mut self) -> &mut Thresholds {
+        &mut self.thresholds
+    }
+
+    fn abort(&self) -> PxcResult<()> {
+        self.abort_signal.abort();
+        Ok(())
+    }
+
+    /// every frame waits for its own trigger
+    fn set_trigger(&mut self, trigger: TriggerMode) -> PxcResult<()> {
+        match trigger {
+            TriggerMode::None | TriggerMode::SwStart => {
+                self.trigger = trigger;
+                Ok(())
+            }
+            _ => Err(PxcErr::NotSupported.into()),
+        }
+    }
+    fn get_trigger(&self) -> TriggerMode {
+        self.trigger
+    }
+    fn is_ready_for_trigger(&self) -> PxcResult<bool> {
+        Ok(self.abort_signal.is_armed())
+    }
+    fn software_trigger(&self) -> PxcResult<()> {
+        self.abort_signal.trigger()
+    }
+
+    fn get_temperature(&self) -> PxcResult<f64> {
+        Ok(SIM_TEMPERATURE)
+    }
+    fn get_bias_voltage(&self) -> PxcResult<f64> {
+        Ok(self.high_voltage)
+    }
+
+    fn set_mode(&mut self, mode: TpxMode) -> PxcResult<()> {
+        self.mode = mode;
+        Ok(())
+    }
+    fn channels(&self) -> (Channel, Option<Channel>) {
+        (self.mode.channel(), None)
+    }
+
+    /// the generated deposits are in keV
+    fn is_calibrated(&self) -> bool {
+        self.mode == TpxMode::Tot
+    }
+    fn capture_energy(&self) -> PxcResult<Vec<f64>> {
+        self.measure()?;
+        let mut rng = self.rng.lock().unwrap();
+        Ok(self
+            .generate(&mut rng)
+            .iter()
+            .map(|&val| val as f64)
+            .collect())
+    }
+}
+
+/// frame being generated, records deposits the way the acquisition mode would
+struct Canvas<'a> {
+    buf: &'a mut PxcBuffer,
+    mode: TpxMode,
+    /// arrival time of the particle being drawn, in clock ticks
+    toa: f64,
+}
+
+impl Canvas<'_> {
+    fn deposit(&mut self, x: isize, y: isize, energy: f64) {
+        if x < 0 || y < 0 || x >= SIM_WIDTH as isize || y >= SIM_HEIGHT as isize {
+            return;
+        }
+        let pixel = &mut self.buf[y as usize * SIM_WIDTH + x as usize];
+        match self.mode {
+            TpxMode::Medipix => *pixel = pixel.saturating_add(1),
+            TpxMode::Tot => *pixel = pixel.saturating_add(energy as c_short),
+            // only the first hit of a pixel is recorded
+            TpxMode::Timepix => {
+                if *pixel == 0 {
+                    *pixel = self.toa as c_short;
+                }
+            }
+        }
+    }
+}
+
+/// straight track with a roughly constant deposit along its length
+fn draw_muon(canvas: &mut Canvas, rng: &mut Rng) {
+    let start_x = rng.range(0.0, SIM_WIDTH as f64);
+    let start_y = rng.range(0.0, SIM_HEIGHT as f64);
+    let angle = rng.range(0.0, std::f64::consts::TAU);
+    let length = rng.range(20.0, 200.0);
+
+    for step in 0..length as usize {
+        let x = start_x + angle.cos() * step as f64;
+        let y = start_y + angle.sin() * step as f64;
+        canvas.deposit(x as isize, y as isize, rng.range(20.0, 40.0));
+    }
+}
+
+/// round blob, brightest in the middle
+fn draw_alpha(canvas: &mut Canvas, rng: &mut Rng) {
+    let center_x = rng.below(SIM_WIDTH) as isize;
+    let center_y = rng.below(SIM_HEIGHT) as isize;
+    let radius = rng.range(3.0, 7.0);
+    let peak = rng.range(200.0, 1000.0);
+
+    let reach = radius.ceil() as isize;
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let distance = ((dx * dx + dy * dy) as f64).sqrt();
+            if distance <= radius {
+                canvas.deposit(
+                    center_x + dx,
+                    center_y + dy,
+                    peak * (1.0 - distance / (radius + 1.0)),
+                );
+            }
+        }
+    }
+}
+
+/// small cluster of a few neighbouring pixels
+fn draw_gamma(canvas: &mut Canvas, rng: &mut Rng) {
+    let mut x = rng.below(SIM_WIDTH) as isize;
+    let mut y = rng.below(SIM_HEIGHT) as isize;
+    for _ in 0..1 + rng.below(4) {
+        canvas.deposit(x, y, rng.range(10.0, 100.0));
+        x += rng.below(3) as isize - 1;
+        y += rng.below(3) as isize - 1;
+    }
+}
+
+/// xorshift64* generator, good enough for fake particles
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must never be zero
+        Self(seed | 1)
+    }
+
+    fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0x2545_f491_4f6c_dd1d);
+        Self::new(nanos)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + self.next_f64() * (high - low)
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next_f64() * max as f64) as usize
+    }
+
+    /// amount of events in an interval with `mean` expected events
+    fn poisson(&mut self, mean: f64) -> usize {
+        if mean <= 0.0 {
+            return 0;
+        }
+        if mean > 30.0 {
+            // normal approximation, Box-Muller
+            let u1 = self.next_f64().max(f64::MIN_POSITIVE);
+            let u2 = self.next_f64();
+            let normal = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+            return (mean + normal * mean.sqrt()).round().max(0.0) as usize;
+        }
+        // Knuth
+        let limit = (-mean).exp();
+        let mut count = 0;
+        let mut product = self.next_f64();
+        while product > limit {
+            count += 1;
+            product *= self.next_f64();
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_rates_works() {
+        let rates = "muon=1.5,noise=0".parse::<SimRates>().unwrap();
+        assert_eq!(rates.muon, 1.5);
+        assert_eq!(rates.noise, 0.0);
+        assert_eq!(rates.alpha, SimRates::default().alpha);
+
+        assert!("muon".parse::<SimRates>().is_err());
+        assert!("pion=1".parse::<SimRates>().is_err());
+        assert!("muon=-1".parse::<SimRates>().is_err());
+    }
+
+    #[test]
+    fn simulated_frames_contain_particles() {
+        let rates = SimRates {
+            muon: 1.0,
+            alpha: 0.0,
+            gamma: 0.0,
+            noise: 0.0,
+        };
+        let mut device = SimDevice::new(rates).seed(42);
+        device.realtime = false;
+        device.set_frame_time(Seconds(5.0)).unwrap();
+
+        let buf = device.capture_image().unwrap();
+        assert!(buf.iter().any(|&val| val > 0));
+
+        let silent = SimDevice {
+            realtime: false,
+            ..SimDevice::new(SimRates { muon: 0.0, ..rates })
+        };
+        assert!(silent.capture_image().unwrap().iter().all(|&val| val == 0));
+    }
+
+    #[test]
+    fn counting_mode_counts_hits() {
+        let mut device = SimDevice::new(SimRates::default()).seed(7);
+        device.realtime = false;
+        device.set_mode(TpxMode::Medipix).unwrap();
+
+        let buf = device.capture_image().unwrap();
+        assert!(buf.iter().any(|&val| val > 0));
+        assert!(buf.iter().all(|&val| val < 20));
+        assert_eq!(device.get_mode(), TpxMode::Medipix);
+    }
+}
@@ -0,0 +1,135 @@
+#![allow(dead_code)]
+
+use crate::api::ffi::{PxcErr, PxcError, PxcResult};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Voltage, e.g. the sensor bias
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Volts(pub f64);
+
+/// Energy deposited in a pixel, or a threshold on it
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct KeV(pub f64);
+
+/// Duration, e.g. the frame time
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Seconds(pub f64);
+
+impl Volts {
+    /// `PxcErr::InvalidArgument` unless the voltage lies within `range`, e.g. `get_voltage_range`
+    pub fn check_range(self, (min, max): (Volts, Volts)) -> PxcResult<Self> {
+        match (min.0..=max.0).contains(&self.0) {
+            true => Ok(self),
+            false => Err(invalid_argument(format!(
+                "bias {self} is outside of {min} - {max}"
+            ))),
+        }
+    }
+}
+
+impl Seconds {
+    /// shortest frame time accepted by `check_frame_time`
+    pub const MIN_FRAME_TIME: Seconds = Seconds(0.00001);
+    /// longest frame time accepted by `check_frame_time`
+    pub const MAX_FRAME_TIME: Seconds = Seconds(3600.0);
+
+    /// `PxcErr::InvalidArgument` unless the frame time is between 10µs and an hour
+    pub fn check_frame_time(self) -> PxcResult<Self> {
+        let (min, max) = (Self::MIN_FRAME_TIME, Self::MAX_FRAME_TIME);
+        match (min.0..=max.0).contains(&self.0) {
+            true => Ok(self),
+            false => Err(invalid_argument(format!(
+                "frame time {self} is outside of {min} - {max}"
+            ))),
+        }
+    }
+
+    pub fn as_duration(self) -> Duration {
+        Duration::from_secs_f64(self.0.max(0.0))
+    }
+}
+
+fn invalid_argument(message: String) -> PxcError {
+    PxcError {
+        message: Some(message),
+        ..PxcErr::InvalidArgument.into()
+    }
+}
+
+impl Display for Volts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}V", self.0)
+    }
+}
+impl Display for KeV {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}keV", self.0)
+    }
+}
+impl Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s", self.0)
+    }
+}
+
+/// parses a number with an optional unit, e.g. both `40` and `40V`
+fn parse_with_unit(s: &str, unit: &str, quantity: &str) -> Result<f64, String> {
+    s.strip_suffix(unit)
+        .unwrap_or(s)
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| format!("Invalid {quantity} '{s}'"))
+}
+
+impl FromStr for Volts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_with_unit(s, "V", "voltage").map(Volts)
+    }
+}
+impl FromStr for KeV {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_with_unit(s, "keV", "energy").map(KeV)
+    }
+}
+impl FromStr for Seconds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_with_unit(s, "s", "time").map(Seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_are_parsed_and_checked() {
+        assert_eq!("40".parse(), Ok(Volts(40.0)));
+        assert_eq!("-300V".parse(), Ok(Volts(-300.0)));
+        assert_eq!("0.5keV".parse(), Ok(KeV(0.5)));
+        assert_eq!("2s".parse(), Ok(Seconds(2.0)));
+        assert!("40keV".parse::<Volts>().is_err());
+        assert!("nan".parse::<Seconds>().is_err());
+
+        let range = (Volts(5.0), Volts(100.0));
+        assert_eq!(Volts(40.0).check_range(range).unwrap(), Volts(40.0));
+        let why = Volts(150.0).check_range(range).unwrap_err();
+        assert_eq!(why, PxcErr::InvalidArgument);
+        assert_eq!(
+            why.to_string(),
+            "invalid argument (bias 150V is outside of 5V - 100V)"
+        );
+
+        assert!(Seconds(0.5).check_frame_time().is_ok());
+        assert!(Seconds(0.0).check_frame_time().is_err());
+        assert!(Seconds(7200.0).check_frame_time().is_err());
+    }
+}
@@ -1,11 +1,43 @@
+#![allow(dead_code)]
 #![allow(clippy::needless_range_loop)]
 
+use crate::api::device::{Channel, Device, PixelHit, Readout, Telemetry, TpxMode};
+use crate::api::ffi::PxcResult;
 use crate::data_worker::particle::Particle;
 use std::collections::{HashMap, HashSet};
+use std::ffi::c_uint;
+use std::ops::ControlFlow;
+use std::time::Instant;
+
+/// how long a batch of back to back frames measures for, in seconds
+const BATCH_SECONDS: f64 = 10.0;
+const MAX_BATCH_FRAMES: usize = 1000;
 
 #[derive(Clone, Default, Debug)]
 pub struct Frame {
     pub data: Vec<Vec<i16>>,
+    /// raw pixel hits, only filled by data driven devices
+    pub hits: Vec<PixelHit>,
+    /// second image of the same acquisition, only filled by dual channel devices
+    pub second_channel: Option<Vec<Vec<f64>>>,
+    /// acquisition mode which produced the frame
+    pub mode: TpxMode,
+    /// what the values in `data` and `second_channel` mean
+    pub channels: (Channel, Option<Channel>),
+    /// deposited energy in keV, only filled by calibrated devices
+    ///
+    /// `data` then holds the energy rounded to whole keV
+    pub energy: Option<Vec<Vec<f64>>>,
+    /// time of arrival in ns, only filled by devices measuring nothing but the ToA
+    ///
+    /// `data` then holds it saturated at 16 bits
+    pub toa: Option<Vec<Vec<f64>>>,
+    /// sensor readings taken right before the batch of the frame was measured
+    pub telemetry: Telemetry,
+    /// fraction of the time since the previous frame of the batch the sensor was measuring
+    pub live_time: Option<f64>,
+    /// position of the frame in the daemon's queue of a device, counting from 0
+    pub sequence: Option<u64>,
     particles: Vec<Particle>,
 }
 
@@ -17,6 +49,127 @@ impl Frame {
         }
     }
 
+    /// image of the hits in the quantity of `channel`, keeping the hits themselves
+    ///
+    /// the ToT is summed up, hits are counted and the ToA is the earliest one of the pixel
+    pub fn from_hits(hits: Vec<PixelHit>, dimensions: (u32, u32), channel: Channel) -> Self {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let mut data = vec![vec![0i16; width]; height];
+        let mut toa = vec![vec![None::<f64>; width]; height];
+        for hit in &hits {
+            let (x, y) = (hit.x as usize, hit.y as usize);
+            let Some(val) = data.get_mut(y).and_then(|row| row.get_mut(x)) else {
+                continue;
+            };
+            match channel {
+                Channel::Tot => *val = val.saturating_add(hit.tot as i16),
+                Channel::Count => *val = val.saturating_add(1),
+                Channel::Toa => {
+                    let earliest = toa[y][x].map_or(hit.toa, |earliest| earliest.min(hit.toa));
+                    toa[y][x] = Some(earliest);
+                    *val = earliest as i16;
+                }
+            }
+        }
+        Self {
+            data,
+            hits,
+            toa: (channel == Channel::Toa).then(|| {
+                toa.into_iter()
+                    .map(|row| row.into_iter().map(|toa| toa.unwrap_or(0.0)).collect())
+                    .collect()
+            }),
+            channels: (channel, None),
+            ..Default::default()
+        }
+    }
+
+    /// amount of frames measured back to back by `capture_batch`
+    pub fn batch_size(device: &dyn Device) -> usize {
+        (BATCH_SECONDS / device.get_frame_time().0).clamp(1.0, MAX_BATCH_FRAMES as f64) as usize
+    }
+
+    /// captures `count` frames back to back, handing each one to `on_frame` once it is read out
+    ///
+    /// devices with a continuous readout skip the setup of every single frame.
+    /// The telemetry is read once before the batch, every frame records it and its live time.
+    /// `on_frame` ends the batch early with `ControlFlow::Break`
+    pub fn capture_batch(
+        device: &dyn Device,
+        count: usize,
+        mut on_frame: impl FnMut(Frame) -> ControlFlow<()>,
+    ) -> PxcResult<()> {
+        let telemetry = device.telemetry()?;
+        let frame_time = device.get_frame_time().0;
+        let mut last_frame = Instant::now();
+        let mut deliver = |frame: Frame| {
+            let elapsed = last_frame.elapsed().as_secs_f64();
+            last_frame = Instant::now();
+            on_frame(Self {
+                live_time: Some((frame_time / elapsed).min(1.0)),
+                telemetry,
+                ..frame
+            })
+        };
+
+        if device.is_data_driven() {
+            for _ in 0..count {
+                if deliver(Self::capture(device)?).is_break() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        device.capture_frames(count, &mut |readout| {
+            deliver(Self::from_readout(device, readout))
+        })
+    }
+
+    /// captures a single frame from the device and applies its software thresholds
+    ///
+    /// calibrated devices fill in the energy, the telemetry is left out
+    pub fn capture(device: &dyn Device) -> PxcResult<Self> {
+        if !device.is_data_driven() {
+            return Ok(Self::from_readout(device, device.capture_readout()?));
+        }
+        let dimensions = device.get_dimensions();
+        let width = dimensions.0 as usize;
+
+        // thresholds apply to every hit instead of the summed up pixels
+        let mut hits = device.capture_hits()?;
+        device.thresholds().retain_hits(&mut hits);
+        let mut frame = Self::from_hits(hits, dimensions, device.channels().0);
+        if device.is_calibrated() {
+            let mut energy = vec![vec![0.0; width]; dimensions.1 as usize];
+            for hit in &frame.hits {
+                if let Some(val) = energy
+                    .get_mut(hit.y as usize)
+                    .and_then(|row| row.get_mut(hit.x as usize))
+                {
+                    *val += hit.tot as f64;
+                }
+            }
+            frame.energy = Some(energy);
+        }
+        frame.mode = device.get_mode();
+        Ok(frame)
+    }
+
+    /// frame of the images of a single acquisition, with the software thresholds applied
+    fn from_readout(device: &dyn Device, readout: Readout) -> Self {
+        let dimensions = device.get_dimensions();
+        let mut frame = Self {
+            second_channel: readout.second.map(|second| to_rows(&second, dimensions)),
+            energy: readout.energy.map(|energy| to_rows(&energy, dimensions)),
+            toa: readout.toa.map(|toa| to_rows(&toa, dimensions)),
+            mode: device.get_mode(),
+            channels: device.channels(),
+            ..Self::new(to_rows(&readout.image, dimensions))
+        };
+        device.thresholds().apply(&mut frame);
+        frame
+    }
+
     pub fn get_particles(&self) -> Vec<Particle> {
         self.particles.clone()
     }
@@ -136,5 +289,78 @@ impl Frame {
         }
 
         self.particles = particle_pixels.into_values().map(Particle::new).collect();
+
+        if let Some(energy) = &self.energy {
+            for particle in self.particles.iter_mut() {
+                particle.energy = Some(
+                    particle
+                        .positions
+                        .iter()
+                        .map(|&(i, j, _)| energy[i][j])
+                        .sum(),
+                );
+            }
+        }
+    }
+}
+
+/// splits a row major buffer into rows, ignoring anything past `width * height`
+fn to_rows<T: Clone>(buffer: &[T], dimensions: (c_uint, c_uint)) -> Vec<Vec<T>> {
+    buffer
+        .chunks(dimensions.0.max(1) as usize)
+        .take(dimensions.1 as usize)
+        .map(|buf| buf.to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(x: u32, y: u32, toa: f64, tot: f32) -> PixelHit {
+        PixelHit { x, y, toa, tot }
+    }
+
+    #[test]
+    fn hits_are_summed_into_image() {
+        let hits = vec![
+            hit(1, 0, 0.0, 5.0),
+            hit(1, 0, 0.0, 3.0),
+            hit(0, 2, 0.0, 7.0),
+        ];
+        let frame = Frame::from_hits(hits, (2, 3), Channel::Tot);
+
+        assert_eq!(frame.data, vec![vec![0, 8], vec![0, 0], vec![7, 0]]);
+        assert_eq!(frame.hits.len(), 3);
+        assert_eq!(frame.toa, None);
+    }
+
+    #[test]
+    fn hits_are_counted_into_image() {
+        let hits = vec![
+            hit(1, 0, 0.0, 5.0),
+            hit(1, 0, 0.0, 3.0),
+            hit(0, 2, 0.0, 7.0),
+        ];
+        let frame = Frame::from_hits(hits, (2, 3), Channel::Count);
+
+        assert_eq!(frame.data, vec![vec![0, 2], vec![0, 0], vec![1, 0]]);
+        assert_eq!(frame.channels, (Channel::Count, None));
+    }
+
+    #[test]
+    fn earliest_toa_is_kept_wide() {
+        let hits = vec![
+            hit(1, 0, 90_000.0, 5.0),
+            hit(1, 0, 70_000.0, 3.0),
+            hit(0, 2, 12.0, 7.0),
+        ];
+        let frame = Frame::from_hits(hits, (2, 3), Channel::Toa);
+
+        assert_eq!(frame.data, vec![vec![0, i16::MAX], vec![0, 0], vec![12, 0]]);
+        assert_eq!(
+            frame.toa,
+            Some(vec![vec![0.0, 70_000.0], vec![0.0, 0.0], vec![12.0, 0.0]])
+        );
     }
 }
@@ -0,0 +1,209 @@
+use crate::data_worker::frame::Frame;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// how many standard deviations above the average occupancy a noisy pixel lies
+const NOISE_SIGMAS: f64 = 5.0;
+/// fraction of the dark frames a noisy pixel is hit in at least,
+/// so that random hits piling up in a pixel do not count
+const MIN_NOISE_OCCUPANCY: f64 = 0.25;
+
+/// Pixels whose values are thrown away, e.g. hot pixels firing without any radiation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PixelMask {
+    /// `(x, y)` of every masked pixel
+    pixels: BTreeSet<(u32, u32)>,
+}
+
+impl PixelMask {
+    /// reads a mask with one masked pixel per line, `x y`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid masked pixel '{line}'"),
+            )
+        };
+        let mut mask = Self::default();
+        for line in BufReader::new(fs::File::open(path)?).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (x, y) = line.split_once(char::is_whitespace).ok_or(invalid(line))?;
+            let x = x.parse().map_err(|_| invalid(line))?;
+            let y = y.trim().parse().map_err(|_| invalid(line))?;
+            mask.insert(x, y);
+        }
+        Ok(mask)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = fs::File::create(path)?;
+        for (x, y) in &self.pixels {
+            writeln!(file, "{x} {y}")?;
+        }
+        file.flush()
+    }
+
+    /// whether the pixel was newly masked
+    pub fn insert(&mut self, x: u32, y: u32) -> bool {
+        self.pixels.insert((x, y))
+    }
+
+    /// whether the pixel was masked
+    pub fn remove(&mut self, x: u32, y: u32) -> bool {
+        self.pixels.remove(&(x, y))
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.pixels.contains(&(x, y))
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    /// `(x, y)` of the masked pixels, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.pixels.iter().copied()
+    }
+
+    /// zeroes out the masked pixels in every image of the frame and drops their hits
+    ///
+    /// has to run before `Frame::count_particles`
+    pub fn apply(&self, frame: &mut Frame) {
+        if self.is_empty() {
+            return;
+        }
+        for &(x, y) in &self.pixels {
+            let (x, y) = (x as usize, y as usize);
+            if let Some(val) = frame.data.get_mut(y).and_then(|row| row.get_mut(x)) {
+                *val = 0;
+            }
+            for image in [&mut frame.second_channel, &mut frame.energy, &mut frame.toa]
+                .into_iter()
+                .flatten()
+            {
+                if let Some(val) = image.get_mut(y).and_then(|row| row.get_mut(x)) {
+                    *val = 0.0;
+                }
+            }
+        }
+        frame.hits.retain(|hit| !self.contains(hit.x, hit.y));
+    }
+}
+
+/// Finds noisy pixels in dark frames, measured without any source and ideally shielded
+///
+/// a pixel is noisy if it was hit in at least a quarter of the frames,
+/// and at least 5 standard deviations more often than the average pixel
+#[derive(Clone, Debug, Default)]
+pub struct NoiseRun {
+    frames: usize,
+    frames_left: usize,
+    /// in how many frames every pixel was hit, by row
+    occupancy: Vec<Vec<u32>>,
+}
+
+impl NoiseRun {
+    pub fn new(frames: usize) -> Self {
+        Self {
+            frames,
+            frames_left: frames,
+            occupancy: Vec::new(),
+        }
+    }
+
+    /// counts the hit pixels of a dark frame, returns the mask once every frame was counted
+    ///
+    /// the frame must not be masked yet
+    pub fn add(&mut self, frame: &Frame) -> Option<PixelMask> {
+        if self.occupancy.is_empty() {
+            self.occupancy = frame.data.iter().map(|row| vec![0; row.len()]).collect();
+        }
+        for (counts, row) in self.occupancy.iter_mut().zip(&frame.data) {
+            for (count, &val) in counts.iter_mut().zip(row) {
+                if val != 0 {
+                    *count += 1;
+                }
+            }
+        }
+
+        self.frames_left = self.frames_left.saturating_sub(1);
+        match self.frames_left {
+            0 => Some(self.mask()),
+            _ => None,
+        }
+    }
+
+    /// mask of the pixels found noisy in the frames counted so far
+    pub fn mask(&self) -> PixelMask {
+        let counts = self.occupancy.iter().flatten().map(|&count| count as f64);
+        let pixels = counts.clone().count().max(1) as f64;
+        let mean = counts.clone().sum::<f64>() / pixels;
+        let deviation = (counts.map(|count| (count - mean).powi(2)).sum::<f64>() / pixels).sqrt();
+        let counted = (self.frames - self.frames_left).max(1) as f64;
+        let limit = (mean + NOISE_SIGMAS * deviation).max(MIN_NOISE_OCCUPANCY * counted);
+
+        let mut mask = PixelMask::default();
+        for (y, row) in self.occupancy.iter().enumerate() {
+            for (x, &count) in row.iter().enumerate() {
+                if count as f64 >= limit {
+                    mask.insert(x as u32, y as u32);
+                }
+            }
+        }
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::device::{Channel, PixelHit};
+
+    #[test]
+    fn noisy_pixels_are_masked() {
+        let mut run = NoiseRun::new(8);
+        let mut dark = vec![vec![0i16; 16]; 16];
+        dark[2][5] = 40;
+        for _ in 0..6 {
+            assert_eq!(run.add(&Frame::new(dark.clone())), None);
+        }
+        // a single hit is no noise
+        dark[7][7] = 3;
+        assert_eq!(run.add(&Frame::new(dark.clone())), None);
+        dark[7][7] = 0;
+        let mask = run.add(&Frame::new(dark)).unwrap();
+        assert_eq!(mask.pixels().collect::<Vec<_>>(), vec![(5, 2)]);
+
+        let hit = |x, y| PixelHit {
+            x,
+            y,
+            toa: 0.0,
+            tot: 1.0,
+        };
+        let mut frame = Frame::from_hits(vec![hit(5, 2), hit(1, 1)], (16, 16), Channel::Tot);
+        mask.apply(&mut frame);
+        assert_eq!(frame.data[2][5], 0);
+        assert_eq!(frame.data[1][1], 1);
+        assert_eq!(frame.hits, vec![hit(1, 1)]);
+
+        let path = std::env::temp_dir().join(format!("pixet_mask_{}.txt", std::process::id()));
+        mask.save(&path).unwrap();
+        assert_eq!(PixelMask::load(&path).unwrap(), mask);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,2 +1,4 @@
 pub mod frame;
+pub mod mask;
 pub mod particle;
+pub mod threshold;
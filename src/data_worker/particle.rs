@@ -2,6 +2,8 @@
 pub struct Particle {
     pub particle_type: ParticleType,
     pub positions: Vec<(usize, usize, i16)>,
+    /// summed up deposited energy in keV, if the frame was calibrated
+    pub energy: Option<f64>,
 }
 
 impl Particle {
@@ -9,6 +11,7 @@ impl Particle {
         Particle {
             particle_type: ParticleType::Unknown,
             positions,
+            energy: None,
         }
     }
 
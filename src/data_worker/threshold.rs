@@ -0,0 +1,180 @@
+use crate::api::device::PixelHit;
+use crate::api::ffi::{PxcErr, PxcError, PxcResult};
+use crate::data_worker::frame::Frame;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Software thresholds, pixels with a value outside of them are zeroed out
+///
+/// values are compared in keV on frames with an `energy`, raw otherwise.
+/// An upper threshold of 0 disables it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    pub low: f64,
+    pub high: f64,
+    /// per pixel lower thresholds, used instead of `low` where given
+    pub low_map: Option<ThresholdMap>,
+    /// per pixel upper thresholds, used instead of `high` where given
+    pub high_map: Option<ThresholdMap>,
+}
+
+/// A threshold for every pixel, by row
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThresholdMap(pub Vec<Vec<f64>>);
+
+impl ThresholdMap {
+    /// reads one row of pixels per line with the values separated by whitespace,
+    /// the same layout as frames saved in the `rak` save mode
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut rows = Vec::new();
+        for line in BufReader::new(fs::File::open(path)?).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let row = line
+                .split_whitespace()
+                .map(|val| {
+                    val.parse::<f64>()
+                        .ok()
+                        .filter(|val| val.is_finite())
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("Invalid threshold '{val}'"),
+                            )
+                        })
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            rows.push(row);
+        }
+        Ok(Self(rows))
+    }
+
+    /// `(width, height)`, the width of the first row
+    pub fn dimensions(&self) -> (u32, u32) {
+        let width = self.0.first().map_or(0, |row| row.len());
+        (width as u32, self.0.len() as u32)
+    }
+
+    /// `PxcErr::InvalidArgument` unless the map covers exactly the pixels of the device
+    pub fn check_dimensions(&self, dimensions: (u32, u32)) -> PxcResult<()> {
+        let (width, height) = self.dimensions();
+        let rectangular = self.0.iter().all(|row| row.len() == width as usize);
+        match rectangular && (width, height) == dimensions {
+            true => Ok(()),
+            false => Err(PxcError {
+                message: Some(format!(
+                    "threshold map of {width}x{height} does not fit {}x{} pixels",
+                    dimensions.0, dimensions.1
+                )),
+                ..PxcErr::InvalidArgument.into()
+            }),
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> Option<f64> {
+        self.0.get(y).and_then(|row| row.get(x)).copied()
+    }
+}
+
+impl Thresholds {
+    /// whether a pixel value lies within the thresholds of the pixel
+    pub fn passes(&self, x: usize, y: usize, value: f64) -> bool {
+        let map_or = |map: &Option<ThresholdMap>, scalar| {
+            map.as_ref().and_then(|map| map.get(x, y)).unwrap_or(scalar)
+        };
+        let (low, high) = (
+            map_or(&self.low_map, self.low),
+            map_or(&self.high_map, self.high),
+        );
+        value >= low && (high == 0.0 || value <= high)
+    }
+
+    /// zeroes out the pixels of the frame outside of the thresholds
+    ///
+    /// calibrated frames are compared by their energy. A second channel is only thresholded
+    /// if it holds the same quantity as the first, e.g. the second counter of a Medipix3
+    pub fn apply(&self, frame: &mut Frame) {
+        for y in 0..frame.data.len() {
+            for x in 0..frame.data[y].len() {
+                let value = match &frame.energy {
+                    Some(energy) => energy[y][x],
+                    None => frame.data[y][x] as f64,
+                };
+                if self.passes(x, y, value) {
+                    continue;
+                }
+                frame.data[y][x] = 0;
+                for image in [&mut frame.energy, &mut frame.toa].into_iter().flatten() {
+                    image[y][x] = 0.0;
+                }
+            }
+        }
+        if frame.channels.1 == Some(frame.channels.0)
+            && let Some(second) = &mut frame.second_channel
+        {
+            for (y, row) in second.iter_mut().enumerate() {
+                for (x, val) in row.iter_mut().enumerate() {
+                    if !self.passes(x, y, *val) {
+                        *val = 0.0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// drops the hits outside of the thresholds by their ToT, in keV on calibrated devices
+    pub fn retain_hits(&self, hits: &mut Vec<PixelHit>) {
+        hits.retain(|hit| self.passes(hit.x as usize, hit.y as usize, hit.tot as f64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::device::Channel;
+
+    #[test]
+    fn thresholds_are_applied_per_pixel() {
+        let thresholds = Thresholds {
+            low: 2.0,
+            high: 0.0,
+            low_map: None,
+            high_map: Some(ThresholdMap(vec![vec![0.0, 5.0], vec![0.0, 0.0]])),
+        };
+        let mut frame = Frame::new(vec![vec![1, 6], vec![300, 4]]);
+        thresholds.apply(&mut frame);
+        assert_eq!(frame.data, vec![vec![0, 0], vec![300, 4]]);
+
+        // calibrated frames are compared in keV
+        let mut frame = Frame::new(vec![vec![2, 3], vec![0, 9]]);
+        frame.energy = Some(vec![vec![1.5, 2.5], vec![0.0, 9.0]]);
+        thresholds.apply(&mut frame);
+        assert_eq!(frame.data, vec![vec![0, 3], vec![0, 9]]);
+        assert_eq!(frame.energy, Some(vec![vec![0.0, 2.5], vec![0.0, 9.0]]));
+
+        let mut frame = Frame::new(vec![vec![3, 3], vec![3, 3]]);
+        frame.second_channel = Some(vec![vec![1.0, 3.0], vec![3.0, 1.0]]);
+        frame.channels = (Channel::Count, Some(Channel::Count));
+        thresholds.apply(&mut frame);
+        assert_eq!(
+            frame.second_channel,
+            Some(vec![vec![0.0, 3.0], vec![3.0, 0.0]])
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("pixet_thresholds_{}.txt", std::process::id()));
+        fs::write(&path, "0 5\n0 0.5\n").unwrap();
+        let map = ThresholdMap::load(&path).unwrap();
+        assert_eq!(map, ThresholdMap(vec![vec![0.0, 5.0], vec![0.0, 0.5]]));
+        assert_eq!(map.dimensions(), (2, 2));
+        assert!(map.check_dimensions((2, 2)).is_ok());
+        assert!(map.check_dimensions((256, 256)).is_err());
+        fs::write(&path, "0 five\n").unwrap();
+        assert!(ThresholdMap::load(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
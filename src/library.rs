@@ -1,79 +1,512 @@
-use crate::api::ffi::PxcIgnoreErr;
-use crate::api::{device::Device, handle::DeviceBuilder};
+use crate::api::device::{Device, DeviceSettings, Tpx2Mode, TpxMode, TriggerMode};
+use crate::api::ffi::{PxcErr, PxcResult};
+use crate::api::handle::{DeviceBuilder, DeviceInfo, PixDirectories, PixHandle};
+use crate::api::replay::{ReplayDevice, ReplayPacing};
+use crate::api::sim::{SimDevice, SimRates};
+use crate::api::units::{KeV, Seconds, Volts};
 use crate::data_worker::frame::Frame;
-use std::collections::HashMap;
+use crate::data_worker::mask::{NoiseRun, PixelMask};
+use crate::data_worker::threshold::ThresholdMap;
+use crate::profile::ProfileStore;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::Display;
 use std::io::{Write, stdout};
+use std::ops::ControlFlow;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
-use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// how often the watcher looks for connected and disconnected detectors
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// how often the capture is aborted again while waiting for it to let go of the device
+const ABORT_RETRY: Duration = Duration::from_millis(10);
+
+/// how often `sync` checks whether every device of the group waits for its trigger
+const SYNC_POLL: Duration = Duration::from_millis(1);
+
+/// how a daemon client refers to a device
+///
+/// pxcore indices change when detectors are replugged, serial numbers do not
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum DeviceId {
+    /// e.g. `0`
+    Index(u32),
+    /// e.g. `serial:12345`
+    Serial(u32),
+}
+
+impl FromStr for DeviceId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("serial:") {
+            Some(serial) => Ok(DeviceId::Serial(serial.parse()?)),
+            None => Ok(DeviceId::Index(s.parse()?)),
+        }
+    }
+}
+
+/// the form `from_str` parses, the way the client addresses the device
+impl std::fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceId::Index(index) => write!(f, "{index}"),
+            DeviceId::Serial(serial) => write!(f, "serial:{serial}"),
+        }
+    }
+}
+
+/// noise run of a device in progress, with the id it was started for
+type SharedNoiseRun = Arc<Mutex<Option<(DeviceId, NoiseRun)>>>;
 
 struct DeviceHolder {
     device: Arc<RwLock<Box<dyn Device>>>,
     buffer_queue: Arc<RwLock<Vec<Frame>>>,
+    /// serial number of hardware devices, used to find them again after a replug
+    serial: Option<u32>,
+    /// pxcore index the hardware device was created with
+    index: Option<u32>,
+    /// settings sent to the device, applied by the capture thread
+    settings: Arc<SentSettings>,
+    capture_thread: Option<JoinHandle<()>>,
+    /// tells the capture thread to end after the current batch
+    stop_capture: Arc<AtomicBool>,
+    /// sequence number the next queued frame gets
+    next_sequence: Arc<AtomicU64>,
+    /// pixels zeroed out in every captured frame
+    mask: Arc<RwLock<PixelMask>>,
+    /// noise run in progress, replaces `mask` once it is done
+    noise_run: SharedNoiseRun,
+}
+
+/// settings sent to a device, shared with its capture thread
+#[derive(Default)]
+struct SentSettings {
+    /// batches waiting for the frame being measured to be read out
+    pending: Mutex<VecDeque<SettingsBatch>>,
+    /// last value of every setting applied, applied again when the device is re-created
+    applied: Mutex<Vec<(String, String)>>,
+}
+
+/// settings of one `set`, applied together
+struct SettingsBatch {
+    settings: Vec<(String, String, Setting)>,
+    /// whether `[applied]` is written once they are applied
+    reply: bool,
+}
+
+impl DeviceHolder {
+    /// `detector` is the connected detector hardware devices were created from
+    fn new(device: Box<dyn Device>, detector: Option<&DeviceInfo>) -> Self {
+        Self {
+            device: Arc::new(RwLock::new(device)),
+            buffer_queue: Arc::new(RwLock::new(Vec::new())),
+            serial: detector.map(|info| info.serial),
+            index: detector.map(|info| info.index),
+            settings: Arc::new(SentSettings::default()),
+            capture_thread: None,
+            stop_capture: Arc::new(AtomicBool::new(false)),
+            next_sequence: Arc::new(AtomicU64::new(0)),
+            mask: Arc::new(RwLock::new(PixelMask::default())),
+            noise_run: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// spawns thread to capture data of device
+    fn start_capture(&mut self) {
+        let device_clone = self.device.clone();
+        let buffer_clone = self.buffer_queue.clone();
+        let stop_clone = Arc::new(AtomicBool::new(false));
+        self.stop_capture = stop_clone.clone();
+        let settings_clone = self.settings.clone();
+        let sequence_clone = self.next_sequence.clone();
+        let mask_clone = self.mask.clone();
+        let noise_clone = self.noise_run.clone();
+        self.capture_thread = Some(thread::spawn(move || {
+            start_dev_loop(
+                device_clone,
+                buffer_clone,
+                stop_clone,
+                settings_clone,
+                sequence_clone,
+                mask_clone,
+                noise_clone,
+            )
+        }));
+    }
+
+    /// interrupts the frame being captured, the capture continues with the next one
+    fn abort(&self) -> PxcResult<()> {
+        // a read lock does not wait for the capture thread
+        self.device.read().unwrap().abort()
+    }
+
+    /// aborts the capture and waits for the capture thread to end
+    fn stop(&mut self) {
+        self.stop_capture.store(true, Ordering::SeqCst);
+        self.abort().report_error();
+        if let Some(thread) = self.capture_thread.take() {
+            // an abort between two frames does not end the batch
+            while !thread.is_finished() {
+                thread::sleep(ABORT_RETRY);
+                let _ = self.abort();
+            }
+            let _ = thread.join();
+        }
+    }
+
+    /// whether the capture thread stopped, either on an error or by panicking
+    fn capture_stopped(&self) -> bool {
+        self.capture_thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+}
+
+/// devices whose measurements `sync` starts together
+struct SyncGroup {
+    ids: Vec<DeviceId>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
 }
 
-pub fn start_library() {
-    let mut devices: HashMap<u32, DeviceHolder> = HashMap::new();
+impl SyncGroup {
+    /// arms the devices with the software trigger and starts triggering them
+    fn start(ids: Vec<DeviceId>, devices: &mut HashMap<DeviceId, DeviceHolder>) -> Self {
+        let mut group = Vec::new();
+        for id in &ids {
+            let holder = devices.get_mut(id).unwrap();
+            set_device_settings(holder, ["trigger", "sw-start"].into_iter(), false);
+            group.push(holder.device.clone());
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        Self {
+            ids,
+            stop,
+            thread: thread::spawn(move || start_sync_loop(group, stop_clone)),
+        }
+    }
+
+    /// stops triggering, the devices still in `devices` measure right away again
+    fn end(self, devices: &mut HashMap<DeviceId, DeviceHolder>) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+        for id in &self.ids {
+            if let Some(holder) = devices.get_mut(id) {
+                set_device_settings(holder, ["trigger", "none"].into_iter(), false);
+            }
+        }
+    }
+}
+
+pub fn start_library(directories: &PixDirectories, profiles: &ProfileStore) {
+    let devices = Arc::new(Mutex::new(HashMap::new()));
     let stdin = std::io::stdin();
-    let handle = crate::api::handle::PixHandle::new();
+    // without pxcore only simulated and replayed devices work
+    let handle = match PixHandle::new(directories) {
+        Ok(handle) => Some(Arc::new(handle)),
+        Err(why) if why == PxcErr::LibraryNotLoaded => {
+            report_error(format!(
+                "{why}, only simulated and replayed devices are available"
+            ));
+            None
+        }
+        Err(why) => {
+            report_error(format!("Failed to initialize pxcore: {why}"));
+            std::process::exit(1);
+        }
+    };
+    let watcher_stop = Arc::new(AtomicBool::new(false));
+    let watcher = handle.clone().map(|handle| {
+        let devices = devices.clone();
+        let stop = watcher_stop.clone();
+        thread::spawn(move || start_watcher(&handle, &devices, &stop))
+    });
+    let mut sync_group: Option<SyncGroup> = None;
     loop {
         let mut input = String::new();
-        if let Err(why) = stdin.read_line(&mut input) {
-            eprintln!("Error reading input: {}", why);
+        let read = stdin.read_line(&mut input);
+        let mut devices = devices.lock().unwrap();
+        match read {
+            // the client closed stdin, leave pxcore without a measurement running
+            Ok(0) => {
+                // keeps the watcher from reattaching the stopped devices
+                watcher_stop.store(true, Ordering::SeqCst);
+                if let Some(group) = sync_group.take() {
+                    group.end(&mut devices);
+                }
+                for holder in devices.values_mut() {
+                    holder.stop();
+                }
+                drop(devices);
+                // the watcher's handle has to be dropped for pxcore to exit
+                if let Some(watcher) = watcher {
+                    watcher.thread().unpark();
+                    let _ = watcher.join();
+                }
+                return;
+            }
+            Ok(_) => {}
+            Err(why) => report_error(format!("Error reading input: {why}")),
         }
+        let device_id = |devices: &HashMap<_, _>, arg| {
+            resolve_id(
+                devices,
+                handle.as_deref(),
+                parse_arg_to_num(arg, DeviceId::Index(0)),
+            )
+        };
         let mut command = input.split_whitespace();
         while let Some(arg) = command.next() {
             match arg {
                 "add" => {
-                    let index: u32 = command.next().unwrap_or("0").parse::<u32>().unwrap_or(0);
-                    if devices.contains_key(&index) {
-                        eprintln!("[err]Device already exists");
-                    }
-                    let builder = DeviceBuilder::new(index);
-                    let device = match handle.get_device(builder) {
-                        Ok(device) => device,
-                        Err(why) => {
-                            eprintln!("[err]Failed to get device: {why:?}");
-                            break;
+                    let mut arg = command.next().unwrap_or("0");
+                    let backend = arg;
+                    if matches!(backend, "sim" | "replay") {
+                        arg = command.next().unwrap_or("0");
+                    }
+                    let id = parse_arg_to_num(Some(arg), DeviceId::Index(0));
+                    if devices.contains_key(&id) {
+                        report_error("Device already exists");
+                        break;
+                    }
+                    let mut command = command.peekable();
+                    let mut detector = None;
+                    let device: Box<dyn Device> = match backend {
+                        "sim" => {
+                            // optional rates, e.g. `add sim 0 muon=1,noise=0`
+                            let rates = match command.next_if(|arg| arg.contains("=")) {
+                                Some(spec) => match spec.parse::<SimRates>() {
+                                    Ok(rates) => rates,
+                                    Err(why) => {
+                                        report_error(format!("Invalid simulation rates: {why}"));
+                                        break;
+                                    }
+                                },
+                                None => SimRates::default(),
+                            };
+                            Box::new(SimDevice::new(rates))
+                        }
+                        "replay" => {
+                            // e.g. `add replay 0 log.txt fast`
+                            let Some(path) = command.next() else {
+                                report_error("Missing replay file");
+                                break;
+                            };
+                            let pacing = match command.next_if_eq(&"fast") {
+                                Some(_) => ReplayPacing::Fast,
+                                None => ReplayPacing::Realtime,
+                            };
+                            match ReplayDevice::open(path, pacing) {
+                                Ok(device) => Box::new(device),
+                                Err(why) => {
+                                    report_error(format!("Failed to open replay file: {why}"));
+                                    break;
+                                }
+                            }
+                        }
+                        _ => {
+                            let Some(handle) = &handle else {
+                                report_error("pxcore is not initialized");
+                                break;
+                            };
+                            let info = match id {
+                                DeviceId::Index(index) => handle.device_info(index),
+                                DeviceId::Serial(serial) => handle.find_device(serial),
+                            };
+                            let info = match info {
+                                Ok(info) => info,
+                                Err(why) => {
+                                    report_error(format!("Failed to find device: {why}"));
+                                    break;
+                                }
+                            };
+                            // the same detector may have been added by its index or serial number
+                            if devices.contains_key(&DeviceId::Serial(info.serial)) {
+                                report_error("Device already exists");
+                                break;
+                            }
+                            let builder = DeviceBuilder::new(info.index);
+                            let device = match handle.get_device(builder) {
+                                Ok((device, ignored)) => {
+                                    ignored.iter().for_each(report_error);
+                                    device
+                                }
+                                Err(why) => {
+                                    report_error(format!("Failed to get device: {why}"));
+                                    break;
+                                }
+                            };
+                            detector = Some(info);
+                            device
                         }
                     };
-                    devices.insert(
-                        index,
-                        DeviceHolder {
-                            device: Arc::new(RwLock::new(Box::new(device))),
-                            buffer_queue: Arc::new(RwLock::new(Vec::new())),
-                        },
-                    );
-                    let device_holder = devices.get(&index).unwrap();
-
-                    let device_clone = device_holder.device.clone();
-                    let buffer_clone = device_holder.buffer_queue.clone();
-
-                    // spawn thread to capture data of device
-                    thread::spawn(move || start_dev_loop(device_clone, buffer_clone));
-
-                    set_device_settings(device_holder, command);
+                    let serial = detector.as_ref().map(|info| info.serial);
+                    // detectors are kept by serial number, which stays the same when replugged
+                    let key = serial.map_or(id, DeviceId::Serial);
+                    let device_holder = devices
+                        .entry(key)
+                        .or_insert(DeviceHolder::new(device, detector.as_ref()));
+                    // settings given with `add` override the detector's default profile
+                    if let Some(serial) = serial
+                        && profiles.exists(serial, ProfileStore::DEFAULT)
+                    {
+                        load_profile(device_holder, profiles, ProfileStore::DEFAULT, false);
+                    }
+                    set_device_settings(device_holder, command, false);
+                    device_holder.start_capture();
                     break;
                 }
                 "set" => {
-                    let index: u32 = command.next().unwrap_or("0").parse::<u32>().unwrap_or(0);
-                    let device_holder = match devices.get(&index) {
+                    let id = device_id(&devices, command.next());
+                    let device_holder = match devices.get_mut(&id) {
                         Some(holder) => holder,
                         None => {
-                            eprintln!("[err]Device not created");
+                            report_error("Device not created");
+                            break;
+                        }
+                    };
+                    // e.g. `set 0 frame-time 1 high-voltage 60`
+                    set_device_settings(device_holder, command, true);
+                    break;
+                }
+                "save-profile" => {
+                    // e.g. `save-profile 0 cosmics`
+                    let id = device_id(&devices, command.next());
+                    let name = command.next().unwrap_or(ProfileStore::DEFAULT);
+                    let Some(holder) = devices.get(&id) else {
+                        report_error("Device not created");
+                        break;
+                    };
+                    let Some(serial) = holder.serial else {
+                        report_error("Only detectors with a serial number have profiles");
+                        break;
+                    };
+                    let settings = profile_settings(
+                        &holder.device.read().unwrap().settings(),
+                        &holder.settings.applied.lock().unwrap(),
+                    );
+                    if let Err(why) = profiles.save(serial, name, &settings) {
+                        report_error(format!("Failed to save profile {name}: {why}"));
+                    }
+                    break;
+                }
+                "load-profile" => {
+                    let id = device_id(&devices, command.next());
+                    let name = command.next().unwrap_or(ProfileStore::DEFAULT);
+                    let Some(holder) = devices.get_mut(&id) else {
+                        report_error("Device not created");
+                        break;
+                    };
+                    load_profile(holder, profiles, name, true);
+                    break;
+                }
+                "mask" => {
+                    // e.g. `mask 0`, `mask 0 add 12 40`, `mask 0 noise 100`
+                    let id = device_id(&devices, command.next());
+                    match devices.get(&id) {
+                        Some(holder) => edit_mask(holder, id, command),
+                        None => report_error("Device not created"),
+                    }
+                    break;
+                }
+                "abort" => {
+                    let id = device_id(&devices, command.next());
+                    match devices.get(&id) {
+                        Some(holder) => holder.abort().report_error(),
+                        None => report_error("Device not created"),
+                    }
+                    break;
+                }
+                "stop" => {
+                    let id = device_id(&devices, command.next());
+                    // the rest of the group would wait for this device forever
+                    if let Some(group) = sync_group.take_if(|group| group.ids.contains(&id)) {
+                        group.end(&mut devices);
+                    }
+                    match devices.remove(&id) {
+                        Some(mut holder) => holder.stop(),
+                        None => report_error("Device not created"),
+                    }
+                    break;
+                }
+                "sync" => {
+                    // e.g. `sync 0 1`, a bare `sync` ends synchronizing
+                    if let Some(group) = sync_group.take() {
+                        group.end(&mut devices);
+                    }
+                    let ids = command
+                        .by_ref()
+                        .map(|arg| match arg.parse::<DeviceId>() {
+                            Ok(id) => Ok(resolve_id(&devices, handle.as_deref(), id)),
+                            Err(_) => Err(arg),
+                        })
+                        .collect::<Result<Vec<_>, _>>();
+                    let ids = match ids {
+                        Ok(ids) => ids,
+                        Err(arg) => {
+                            report_error(format!("Invalid device: {arg}"));
                             break;
                         }
                     };
-                    set_device_settings(device_holder, command);
+                    if let Some(id) = ids.iter().find(|id| !devices.contains_key(id)) {
+                        report_error(format!("Device not created: {id:?}"));
+                        break;
+                    }
+                    if !ids.is_empty() {
+                        sync_group = Some(SyncGroup::start(ids, &mut devices));
+                    }
+                    break;
+                }
+                "list" => {
+                    let Some(handle) = &handle else {
+                        report_error("pxcore is not initialized");
+                        break;
+                    };
+                    match handle.list_devices() {
+                        Ok(devices) => print_devices(&devices),
+                        Err(why) => report_error(format!("Failed to list devices: {why}")),
+                    }
+                    break;
+                }
+                "status" => {
+                    let id = device_id(&devices, command.next());
+                    match devices.get(&id) {
+                        Some(holder) => match holder.device.read().unwrap().telemetry() {
+                            Ok(telemetry) => {
+                                writeln!(stdout().lock(), "[status]{:?}", telemetry).unwrap()
+                            }
+                            Err(why) => report_error(format!("Failed to read telemetry: {why}")),
+                        },
+                        None => report_error("Device not created"),
+                    }
+                    break;
+                }
+                "settings" => {
+                    let id = device_id(&devices, command.next());
+                    match devices.get(&id) {
+                        Some(holder) => {
+                            let settings = holder.device.read().unwrap().settings();
+                            writeln!(stdout().lock(), "[settings]{}", settings.to_json()).unwrap();
+                        }
+                        None => report_error("Device not created"),
+                    }
                     break;
                 }
                 "get" => {
-                    let index: u32 = command.next().unwrap_or("0").parse::<u32>().unwrap_or(0);
-                    match devices.get_mut(&index) {
+                    let id = device_id(&devices, command.next());
+                    // the energy of every pixel multiplies the size of a frame, it is only sent on request
+                    let with_energy = command.next() == Some("energy");
+                    match devices.get_mut(&id) {
                         Some(holder) => {
-                            print_buffers(holder);
+                            print_buffers(holder, with_energy);
                         }
-                        None => eprintln!("[err]Device not created"),
+                        None => report_error("Device not created"),
                     }
                     break;
                 }
@@ -83,77 +516,538 @@ pub fn start_library() {
     }
 }
 
-fn set_device_settings<'a>(holder: &DeviceHolder, mut command: impl Iterator<Item = &'a str>) {
-    let device_clone = holder.device.clone();
-    while let Some(arg) = command.next() {
-        match arg {
-            "frame-time" => {
-                let mut device = device_clone.write().unwrap();
-                device
-                    .set_frame_time(parse_arg_to_num(command.next(), crate::FRAME_TIME_DEFAULT))
-                    .ignore_error();
+/// applies the settings of a `set` command together, between two frames
+///
+/// every setting is parsed first, nothing is applied if one of them is invalid.
+/// A running capture applies them once the frame being measured is read out, see `apply_settings`,
+/// the daemon does not wait for it. A measurement still waiting for its software trigger is aborted
+fn set_device_settings<'a>(
+    holder: &mut DeviceHolder,
+    mut command: impl Iterator<Item = &'a str>,
+    reply: bool,
+) {
+    let mut batch = Vec::new();
+    let mut valid = true;
+    while let Some(key) = command.next() {
+        let value = command.next();
+        match Setting::parse(key, value) {
+            Ok(setting) => batch.push((
+                key.to_owned(),
+                value.unwrap_or_default().to_owned(),
+                setting,
+            )),
+            Err(why) => {
+                report_error(format!("Failed to set {key}: {why}"));
+                valid = false;
             }
-            "threshold-max" => {
-                let mut device = device_clone.write().unwrap();
-                device.set_software_high_threshold(parse_arg_to_num(
-                    command.next(),
-                    crate::THRESHOLD_MAX_DEFAULT,
-                ));
+        }
+    }
+    if !valid {
+        return;
+    }
+    let batch = SettingsBatch {
+        settings: batch,
+        reply,
+    };
+
+    if holder.capture_stopped() {
+        let mut device = holder.device.write().unwrap_or_else(|why| why.into_inner());
+        apply_settings(
+            device.as_mut(),
+            batch,
+            &holder.settings,
+            &holder.next_sequence,
+        );
+        return;
+    }
+    holder.settings.pending.lock().unwrap().push_back(batch);
+    // nothing was measured yet, the trigger may never come
+    let device = holder.device.read().unwrap();
+    if device.is_ready_for_trigger().unwrap_or(false) {
+        let _ = device.abort();
+    }
+}
+
+/// applies a batch of settings, if the device rejects one of them the ones applied before are undone
+///
+/// writes `[applied]` with the sequence number of the first frame captured with the new settings
+/// if the batch asks for it, and returns that number
+fn apply_settings(
+    device: &mut dyn Device,
+    batch: SettingsBatch,
+    settings: &SentSettings,
+    next_sequence: &AtomicU64,
+) -> Option<u64> {
+    let mut valid = true;
+    for (key, _, setting) in &batch.settings {
+        if let Err(why) = setting.check(device) {
+            report_error(format!("Failed to set {key}: {why}"));
+            valid = false;
+        }
+    }
+    if !valid {
+        return None;
+    }
+    let previous = device.settings();
+    let thresholds = device.thresholds().clone();
+    let mut undo: Vec<(&str, Setting)> = Vec::new();
+    for (key, _, setting) in &batch.settings {
+        let restore = setting.restoring(&previous);
+        if let Err(why) = setting.clone().apply(device) {
+            report_error(format!("Failed to set {key}: {why}"));
+            for (key, setting) in undo.into_iter().rev() {
+                if let Err(why) = setting.apply(device) {
+                    report_error(format!("Failed to restore {key}: {why}"));
+                }
             }
-            "threshold-min" => {
-                let mut device = device_clone.write().unwrap();
-                device.set_software_low_threshold(parse_arg_to_num(
-                    command.next(),
-                    crate::THRESHOLD_MIN_DEFAULT,
-                ));
+            *device.thresholds_mut() = thresholds;
+            return None;
+        }
+        undo.extend(restore.map(|setting| (key.as_str(), setting)));
+    }
+    let mut applied = settings.applied.lock().unwrap();
+    for (key, value, _) in batch.settings {
+        applied.retain(|(saved, _)| *saved != key);
+        applied.push((key, value));
+    }
+    // applied between two frames, every frame queued so far used the old settings
+    let sequence = next_sequence.load(Ordering::SeqCst);
+    if batch.reply {
+        writeln!(stdout().lock(), "[applied]{sequence}").unwrap();
+    }
+    Some(sequence)
+}
+
+/// the settings a device is running with, as the `key value` lines of a profile
+///
+/// threshold maps are only known by the files they were loaded from, so they are taken from
+/// the settings sent to the device. The trigger depends on how the detectors are wired and synchronized
+fn profile_settings(settings: &DeviceSettings, sent: &[(String, String)]) -> Vec<(String, String)> {
+    let mut profile = vec![("frame-time".to_owned(), settings.frame_time.to_string())];
+    let mut push = |key: &str, value: Option<String>| {
+        profile.extend(value.map(|value| (key.to_owned(), value)));
+    };
+    push("high-voltage", settings.high_voltage.map(|v| v.to_string()));
+    push("threshold-pix", settings.threshold.map(|t| t.to_string()));
+    push(
+        "threshold-pix-2",
+        settings.second_threshold.map(|t| t.to_string()),
+    );
+    push("threshold-min", Some(settings.low_threshold.to_string()));
+    push("threshold-max", Some(settings.high_threshold.to_string()));
+    // Medipix3 and Timepix2 devices have no `mode` to set
+    match (settings.charge_summing, settings.tpx2_mode) {
+        (None, None) => push("mode", Some(settings.mode.to_string())),
+        (charge_summing, tpx2_mode) => {
+            push("charge-summing", charge_summing.map(|c| c.to_string()));
+            push("tpx2-mode", tpx2_mode.map(|m| m.to_string()));
+        }
+    }
+    profile.extend(
+        sent.iter()
+            .filter(|(key, _)| key == "threshold-min-map" || key == "threshold-max-map")
+            .cloned(),
+    );
+    profile
+}
+
+/// applies the profile `name` of the holder's detector like a single `set`
+fn load_profile(holder: &mut DeviceHolder, profiles: &ProfileStore, name: &str, reply: bool) {
+    let Some(serial) = holder.serial else {
+        report_error("Only detectors with a serial number have profiles");
+        return;
+    };
+    let settings = match profiles.load(serial, name) {
+        Ok(settings) => settings,
+        Err(why) => {
+            report_error(format!("Failed to load profile {name}: {why}"));
+            return;
+        }
+    };
+    let command = settings
+        .iter()
+        .flat_map(|(key, value)| [key.as_str(), value.as_str()]);
+    set_device_settings(holder, command, reply)
+}
+
+/// a setting understood by `set`, followed by its value, e.g. `high-voltage 60`
+#[derive(Clone, Debug, PartialEq)]
+enum Setting {
+    FrameTime(Seconds),
+    ThresholdMax(f64),
+    ThresholdMin(f64),
+    /// read from the file when parsed, `None` for `none`
+    ThresholdMaxMap(Option<ThresholdMap>),
+    ThresholdMinMap(Option<ThresholdMap>),
+    ThresholdPix(KeV),
+    ThresholdPix2(KeV),
+    ChargeSumming(bool),
+    Mode(TpxMode),
+    Tpx2Mode(Tpx2Mode),
+    Trigger(TriggerMode),
+    HighVoltage(Volts),
+}
+
+impl Setting {
+    fn parse(key: &str, value: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let value = value.ok_or("missing value")?;
+        let setting = match key {
+            "frame-time" => Setting::FrameTime(value.parse()?),
+            "threshold-max" => Setting::ThresholdMax(value.parse()?),
+            "threshold-min" => Setting::ThresholdMin(value.parse()?),
+            "threshold-max-map" => Setting::ThresholdMaxMap(load_threshold_map(value)?),
+            "threshold-min-map" => Setting::ThresholdMinMap(load_threshold_map(value)?),
+            "threshold-pix" => Setting::ThresholdPix(value.parse()?),
+            "threshold-pix-2" => Setting::ThresholdPix2(value.parse()?),
+            "charge-summing" => Setting::ChargeSumming(value.parse()?),
+            "mode" => Setting::Mode(value.parse()?),
+            "tpx2-mode" => Setting::Tpx2Mode(value.parse()?),
+            "trigger" => Setting::Trigger(value.parse()?),
+            "high-voltage" => Setting::HighVoltage(value.parse()?),
+            _ => return Err(format!("unknown setting {key}").into()),
+        };
+        Ok(setting)
+    }
+
+    /// rejects values out of the device's range before any setting of the batch is applied
+    fn check(&self, device: &dyn Device) -> PxcResult<()> {
+        match self {
+            Setting::FrameTime(frame_time) => frame_time.check_frame_time().map(|_| ()),
+            Setting::ThresholdMaxMap(Some(map)) | Setting::ThresholdMinMap(Some(map)) => {
+                map.check_dimensions(device.get_dimensions())
             }
-            "threshold-pix" => {
-                let device = device_clone.write().unwrap();
-                device
-                    .set_threshold(parse_arg_to_num(
-                        command.next(),
-                        crate::THRESHOLD_PIX_DEFAULT,
-                    ))
-                    .ignore_error();
+            Setting::HighVoltage(voltage) => match device.get_voltage_range() {
+                Ok(range) => voltage.check_range(range).map(|_| ()),
+                // left to `set_high_voltage` to report
+                Err(_) => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// setting which restores the value this one replaces
+    ///
+    /// `None` for the software thresholds, which are restored as a whole, and values never set
+    fn restoring(&self, previous: &DeviceSettings) -> Option<Self> {
+        match self {
+            Setting::FrameTime(_) => Some(Setting::FrameTime(previous.frame_time)),
+            Setting::ThresholdMax(_)
+            | Setting::ThresholdMin(_)
+            | Setting::ThresholdMaxMap(_)
+            | Setting::ThresholdMinMap(_) => None,
+            Setting::ThresholdPix(_) => previous.threshold.map(Setting::ThresholdPix),
+            Setting::ThresholdPix2(_) => previous.second_threshold.map(Setting::ThresholdPix2),
+            Setting::ChargeSumming(_) => previous.charge_summing.map(Setting::ChargeSumming),
+            Setting::Mode(_) => Some(Setting::Mode(previous.mode)),
+            Setting::Tpx2Mode(_) => previous.tpx2_mode.map(Setting::Tpx2Mode),
+            Setting::Trigger(_) => Some(Setting::Trigger(previous.trigger)),
+            Setting::HighVoltage(_) => previous.high_voltage.map(Setting::HighVoltage),
+        }
+    }
+
+    fn apply(self, device: &mut dyn Device) -> PxcResult<()> {
+        match self {
+            Setting::FrameTime(frame_time) => device.set_frame_time(frame_time),
+            Setting::ThresholdMax(threshold) => {
+                device.set_software_high_threshold(threshold);
+                Ok(())
+            }
+            Setting::ThresholdMin(threshold) => {
+                device.set_software_low_threshold(threshold);
+                Ok(())
+            }
+            Setting::ThresholdMaxMap(map) => {
+                device.thresholds_mut().high_map = map;
+                Ok(())
             }
-            "high-voltage" => {
-                let device = device_clone.write().unwrap();
-                device
-                    .set_high_voltage(parse_arg_to_num(
-                        command.next(),
-                        crate::HIGH_VOLTAGE_DEFAULT,
-                    ))
-                    .ignore_error();
+            Setting::ThresholdMinMap(map) => {
+                device.thresholds_mut().low_map = map;
+                Ok(())
             }
-            _ => eprintln!("Invalid command: {arg}"),
+            Setting::ThresholdPix(threshold) => device.set_threshold(threshold),
+            Setting::ThresholdPix2(threshold) => device.set_indexed_threshold(1, threshold),
+            Setting::ChargeSumming(enabled) => device.set_charge_summing(enabled),
+            Setting::Mode(mode) => device.set_mode(mode),
+            Setting::Tpx2Mode(mode) => device.set_tpx2_mode(mode),
+            Setting::Trigger(trigger) => device.set_trigger(trigger),
+            Setting::HighVoltage(voltage) => device.set_high_voltage(voltage),
         }
     }
 }
 
-fn start_dev_loop(device: Arc<RwLock<Box<dyn Device>>>, buffer: Arc<RwLock<Vec<Frame>>>) {
-    loop {
+/// `none` removes the map
+fn load_threshold_map(path: &str) -> std::io::Result<Option<ThresholdMap>> {
+    match path {
+        "none" => Ok(None),
+        path => ThresholdMap::load(path).map(Some),
+    }
+}
+
+/// periodically looks for connected and disconnected detectors until `stop` is set
+///
+/// devices whose capture stopped are re-created with their last settings
+/// once a detector with the same serial number is connected again.
+/// Devices whose detector changed its index are stopped and re-created at the new one
+/// after their capture ended
+fn start_watcher(
+    handle: &PixHandle,
+    devices: &Mutex<HashMap<DeviceId, DeviceHolder>>,
+    stop: &AtomicBool,
+) {
+    let mut attached = handle.list_devices().unwrap_or_default();
+    while !stop.load(Ordering::SeqCst) {
+        // unparked when the daemon stops
+        thread::park_timeout(WATCH_INTERVAL);
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        handle.refresh_devices();
+        let current = match handle.list_devices() {
+            Ok(current) => current,
+            Err(why) => {
+                report_error(format!("Failed to list devices: {why}"));
+                continue;
+            }
+        };
+
+        for info in &current {
+            if !attached.iter().any(|old| old.serial == info.serial) {
+                report_event(format!("connected {info:?}"));
+            }
+        }
+        for info in &attached {
+            if !current.iter().any(|new| new.serial == info.serial) {
+                report_event(format!("disconnected {info:?}"));
+            }
+        }
+        attached = current;
+
+        let mut devices = devices.lock().unwrap();
+        // the daemon stopped every capture while the list was read
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        for holder in devices.values_mut() {
+            let Some(serial) = holder.serial else {
+                continue;
+            };
+            let info = attached.iter().find(|info| info.serial == serial);
+            if !holder.capture_stopped() {
+                if info.map(|info| info.index) == holder.index {
+                    continue;
+                }
+                // the old index may belong to another detector now, so it is not aborted.
+                // The capture ends after its frame and is re-created once it did, without `devices` held meanwhile
+                if !holder.stop_capture.swap(true, Ordering::SeqCst) {
+                    report_event(format!(
+                        "stopping capture of serial {serial}, it is no longer at index {:?}",
+                        holder.index
+                    ));
+                }
+                continue;
+            }
+            let Some(info) = info else {
+                continue;
+            };
+            match handle.get_device(DeviceBuilder::new(info.index)) {
+                Ok((device, ignored)) => {
+                    ignored.iter().for_each(report_error);
+                    holder.device.clear_poison();
+                    let mut device_lock = holder.device.write().unwrap();
+                    *device_lock = device;
+                    for (key, value) in holder.settings.applied.lock().unwrap().iter() {
+                        let result = Setting::parse(key, Some(value))
+                            .and_then(|setting| Ok(setting.apply(device_lock.as_mut())?));
+                        if let Err(why) = result {
+                            report_error(format!("Failed to set {key}: {why}"));
+                        }
+                    }
+                    drop(device_lock);
+                    holder.index = Some(info.index);
+                    holder.start_capture();
+                    report_event(format!("reattached {info:?}"));
+                }
+                Err(why) => report_error(format!("Failed to reattach device: {why}")),
+            }
+        }
+    }
+}
+
+fn start_dev_loop(
+    device: Arc<RwLock<Box<dyn Device>>>,
+    buffer: Arc<RwLock<Vec<Frame>>>,
+    stop: Arc<AtomicBool>,
+    settings: Arc<SentSettings>,
+    next_sequence: Arc<AtomicU64>,
+    mask: Arc<RwLock<PixelMask>>,
+    noise_run: SharedNoiseRun,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        // settings sent during the last batch apply before the next one
+        let pending = std::mem::take(&mut *settings.pending.lock().unwrap());
+        if !pending.is_empty() {
+            let mut device = device.write().unwrap();
+            for batch in pending {
+                apply_settings(device.as_mut(), batch, &settings, &next_sequence);
+            }
+        }
         let device = device.read().unwrap();
-        let out_buf = device.capture_image().unwrap();
-        let dimensions = device.get_dimensions();
-        // early drop to release lock
+        // frames are queued as soon as they are read out
+        let result = Frame::capture_batch(
+            device.as_ref(),
+            Frame::batch_size(device.as_ref()),
+            |mut frame| {
+                frame.sequence = Some(next_sequence.fetch_add(1, Ordering::SeqCst));
+                finish_noise_run(&noise_run, &mask, &frame);
+                mask.read().unwrap().apply(&mut frame);
+                buffer.write().unwrap().push(frame);
+                // new settings and stopping wait for the frame being measured
+                match stop.load(Ordering::SeqCst) || !settings.pending.lock().unwrap().is_empty() {
+                    true => ControlFlow::Break(()),
+                    false => ControlFlow::Continue(()),
+                }
+            },
+        );
+        // early drop to release lock, settings are applied between batches
         drop(device);
-        let image = out_buf
-            .chunks(dimensions.0 as usize)
-            .map(|buf| buf.to_vec())
+
+        match result {
+            Ok(()) => {}
+            // the aborted frame is dropped
+            Err(why) if why == PxcErr::AcqAborted => {}
+            Err(why) => {
+                report_error(format!("Stopping capture: {why}"));
+                return;
+            }
+        }
+    }
+}
+
+/// counts the unmasked frame into the noise run, if one is in progress
+fn finish_noise_run(
+    noise_run: &Mutex<Option<(DeviceId, NoiseRun)>>,
+    mask: &RwLock<PixelMask>,
+    frame: &Frame,
+) {
+    let mut noise_run = noise_run.lock().unwrap();
+    let Some((id, run)) = noise_run.as_mut() else {
+        return;
+    };
+    if let Some(noisy) = run.add(frame) {
+        report_event(format!(
+            "noise run finished on {id}, {} pixels masked",
+            noisy.len()
+        ));
+        *mask.write().unwrap() = noisy;
+        *noise_run = None;
+    }
+}
+
+/// starts the measurements of `devices` together, once all of them wait for their trigger
+fn start_sync_loop(devices: Vec<Arc<RwLock<Box<dyn Device>>>>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::SeqCst) {
+        let group = devices
+            .iter()
+            .map(|device| device.read().unwrap())
             .collect::<Vec<_>>();
-        let frame = Frame::new(image);
+        let ready = group
+            .iter()
+            .map(|device| device.is_ready_for_trigger())
+            .collect::<PxcResult<Vec<_>>>();
+        match ready {
+            Ok(ready) if ready.iter().all(|&ready| ready) => {
+                // the locks are already held, so the triggers go out back to back
+                for device in &group {
+                    if let Err(why) = device.software_trigger() {
+                        report_error(format!("Stopping synchronized start: {why}"));
+                        return;
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(why) => {
+                report_error(format!("Stopping synchronized start: {why}"));
+                return;
+            }
+        }
+        drop(group);
+        thread::sleep(SYNC_POLL);
+    }
+}
 
-        let mut buf_mut = buffer.write().unwrap();
-        buf_mut.push(frame);
+/// runs a `mask` command, a bare `mask <index>` prints the masked pixels
+fn edit_mask<'a>(holder: &DeviceHolder, id: DeviceId, mut command: impl Iterator<Item = &'a str>) {
+    let mut mask = holder.mask.write().unwrap();
+    let action = command.next();
+    let mut pixel = || -> Option<(u32, u32)> {
+        let x = command.next()?.parse().ok()?;
+        let y = command.next()?.parse().ok()?;
+        Some((x, y))
+    };
+    match action {
+        None => print_mask(&mask),
+        Some("add") => match pixel() {
+            Some((x, y)) => {
+                mask.insert(x, y);
+            }
+            None => report_error("Invalid pixel, expected `x y`"),
+        },
+        Some("remove") => match pixel() {
+            Some((x, y)) => {
+                mask.remove(x, y);
+            }
+            None => report_error("Invalid pixel, expected `x y`"),
+        },
+        Some("clear") => mask.clear(),
+        Some("load") => match command.next().map(PixelMask::load) {
+            Some(Ok(loaded)) => *mask = loaded,
+            Some(Err(why)) => report_error(format!("Failed to load mask: {why}")),
+            None => report_error("Missing mask file"),
+        },
+        Some("save") => match command.next().map(|path| mask.save(path)) {
+            Some(Ok(())) => {}
+            Some(Err(why)) => report_error(format!("Failed to save mask: {why}")),
+            None => report_error("Missing mask file"),
+        },
+        Some("noise") => match command.next().map(str::parse::<usize>) {
+            Some(Ok(frames)) if frames > 0 => {
+                *holder.noise_run.lock().unwrap() = Some((id, NoiseRun::new(frames)));
+            }
+            _ => report_error("Invalid amount of dark frames"),
+        },
+        Some(arg) => report_error(format!("Invalid command: {arg}")),
     }
 }
 
-fn print_buffers(holder: &mut DeviceHolder) {
+fn print_mask(mask: &PixelMask) {
+    let mut stdout = stdout().lock();
+
+    // print number of masked pixels that can be expected
+    writeln!(stdout, "[len]{}", mask.len()).unwrap();
+
+    for (x, y) in mask.pixels() {
+        writeln!(stdout, "[pixel]{x} {y}").unwrap();
+    }
+}
+
+fn print_devices(devices: &[DeviceInfo]) {
+    let mut stdout = stdout().lock();
+
+    // print number of devices that can be expected
+    writeln!(stdout, "[len]{}", devices.len()).unwrap();
+
+    for info in devices {
+        writeln!(stdout, "[device]{:?}", info).unwrap();
+    }
+}
+
+/// prints the captured frames, their `energy` only if `with_energy` is set
+fn print_buffers(holder: &mut DeviceHolder, with_energy: bool) {
     let buffer_queue_clone = holder.buffer_queue.clone();
     let buffer_queue = match buffer_queue_clone.read() {
         Ok(queue) => queue,
         Err(why) => {
-            eprintln!("[err]Failed to read buffer queue: {why:?}");
+            report_error(format!("Failed to read buffer queue: {why:?}"));
             return;
         }
     };
@@ -165,7 +1059,52 @@ fn print_buffers(holder: &mut DeviceHolder) {
     writeln!(stdout, "[len]{}", len).unwrap();
 
     for frame in buffer_queue.iter() {
-        writeln!(stdout, "[frame]{:?}", frame).unwrap();
+        match with_energy || frame.energy.is_none() {
+            true => writeln!(stdout, "[frame]{:?}", frame).unwrap(),
+            false => {
+                let mut frame = frame.clone();
+                frame.energy = None;
+                writeln!(stdout, "[frame]{:?}", frame).unwrap()
+            }
+        }
+    }
+}
+
+/// helper trait to report errors to the client instead of ignoring them
+trait PxcReportErr {
+    fn report_error(self);
+}
+impl<T> PxcReportErr for PxcResult<T> {
+    fn report_error(self) {
+        if let Err(why) = self {
+            report_error(why);
+        }
+    }
+}
+
+/// writes an event line for the client to stdout
+fn report_event(event: impl Display) {
+    writeln!(stdout().lock(), "[event]{event}").unwrap();
+}
+
+/// writes an error line for the client to stdout
+fn report_error(why: impl Display) {
+    writeln!(stdout().lock(), "[err]{why}").unwrap();
+}
+
+/// key in `devices` of the device a client refers to
+///
+/// detectors are kept by serial number, an index refers to the detector at that index
+fn resolve_id(
+    devices: &HashMap<DeviceId, DeviceHolder>,
+    handle: Option<&PixHandle>,
+    id: DeviceId,
+) -> DeviceId {
+    match (id, handle) {
+        (DeviceId::Index(index), Some(handle)) if !devices.contains_key(&id) => handle
+            .device_info(index)
+            .map_or(id, |info| DeviceId::Serial(info.serial)),
+        _ => id,
     }
 }
 
@@ -176,11 +1115,11 @@ where
 {
     match arg {
         Some(val) => val.parse::<T>().unwrap_or_else(|why| {
-            eprintln!("[err]Error parsing number {arg:?}: {why:?}");
+            report_error(format!("Error parsing number {arg:?}: {why:?}"));
             default
         }),
         None => {
-            eprintln!("[err]Error parsing command: None");
+            report_error("Error parsing command: None");
             default
         }
     }
@@ -200,5 +1139,104 @@ mod tests {
 
         let num = parse_arg_to_num(None, 0);
         assert_eq!(num, 0);
+
+        let id = parse_arg_to_num(Some("serial:12345"), DeviceId::Index(0));
+        assert_eq!(id, DeviceId::Serial(12345));
+        let id = parse_arg_to_num(Some("3"), DeviceId::Index(0));
+        assert_eq!(id, DeviceId::Index(3));
+        let id = parse_arg_to_num(Some("serial:"), DeviceId::Index(0));
+        assert_eq!(id, DeviceId::Index(0));
+
+        assert_eq!(DeviceId::Serial(12345).to_string(), "serial:12345");
+        assert_eq!(DeviceId::Index(3).to_string(), "3");
+    }
+
+    #[test]
+    fn settings_are_applied_all_or_nothing() {
+        assert_eq!(
+            Setting::parse("high-voltage", Some("60V")).unwrap(),
+            Setting::HighVoltage(Volts(60.0))
+        );
+        assert!(Setting::parse("frame-time", None).is_err());
+        assert!(Setting::parse("bias", Some("60")).is_err());
+
+        let mut device = SimDevice::new(SimRates::default());
+        let settings = SentSettings::default();
+        let next_sequence = AtomicU64::new(0);
+        let mut apply = |command: &str| {
+            let batch = SettingsBatch {
+                settings: command
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .chunks(2)
+                    .map(|pair| {
+                        let setting = Setting::parse(pair[0], Some(pair[1])).unwrap();
+                        (pair[0].to_owned(), pair[1].to_owned(), setting)
+                    })
+                    .collect(),
+                reply: false,
+            };
+            apply_settings(&mut device, batch, &settings, &next_sequence)
+        };
+        assert_eq!(apply("frame-time 1 high-voltage 150"), None);
+        assert!(settings.applied.lock().unwrap().is_empty());
+
+        next_sequence.store(7, Ordering::SeqCst);
+        assert_eq!(apply("frame-time 1 high-voltage 60"), Some(7));
+        assert_eq!(settings.applied.lock().unwrap().len(), 2);
+
+        // simulated devices have no charge summing, which only shows when it is applied
+        assert_eq!(
+            apply("frame-time 0.5 threshold-min 3 charge-summing true"),
+            None
+        );
+        assert_eq!(device.get_frame_time(), Seconds(1.0));
+        assert_eq!(device.get_software_low_threshold(), 0.0);
+        assert_eq!(settings.applied.lock().unwrap().len(), 2);
+
+        // without a capture running they are applied right away
+        let mut holder = DeviceHolder::new(Box::new(SimDevice::new(SimRates::default())), None);
+        set_device_settings(&mut holder, "frame-time 1".split_whitespace(), false);
+        assert_eq!(holder.device.read().unwrap().get_frame_time(), Seconds(1.0));
+    }
+
+    #[test]
+    fn profiles_store_the_running_settings() {
+        let settings = DeviceSettings {
+            frame_time: Seconds(0.5),
+            high_voltage: Some(Volts(60.0)),
+            threshold: None,
+            low_threshold: 3.0,
+            high_threshold: 0.0,
+            mode: TpxMode::Tot,
+            trigger: TriggerMode::SwStart,
+            second_threshold: None,
+            charge_summing: None,
+            tpx2_mode: None,
+        };
+        let sent = [
+            ("threshold-min-map".to_owned(), "low.txt".to_owned()),
+            ("trigger".to_owned(), "sw-start".to_owned()),
+            ("high-voltage".to_owned(), "40".to_owned()),
+        ];
+        let profile = profile_settings(&settings, &sent);
+        let lines = profile
+            .iter()
+            .map(|(key, value)| format!("{key} {value}"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "frame-time 0.5s",
+                "high-voltage 60V",
+                "threshold-min 3",
+                "threshold-max 0",
+                "mode tot",
+                "threshold-min-map low.txt",
+            ]
+        );
+        for (key, value) in &profile {
+            assert!(Setting::parse(key, Some(value)).is_ok() || key == "threshold-min-map");
+        }
     }
 }
@@ -1,40 +1,92 @@
 use crate::{
-    api::device::Device,
+    api::device::{Device, DeviceSettings, TpxMode},
     api::ffi::PxcIgnoreErr,
+    api::ffi::{PxcErr, PxcResult},
+    api::handle::{PixDirectories, THRESHOLD_DEFAULT},
+    api::replay::{ReplayDevice, ReplayPacing, SETTINGS_PREFIX},
+    api::sim::{SimDevice, SimRates},
+    api::units::{KeV, Seconds, Volts},
     data_worker::{
         frame::Frame,
+        mask::{NoiseRun, PixelMask},
         particle::{Particle, ParticleType},
+        threshold::ThresholdMap,
     },
+    profile::ProfileStore,
 };
-use std::{io::Write, path::Path};
+use std::{io::Write, ops::ControlFlow, path::Path};
 
 mod api;
 mod data_worker;
 mod library;
+mod profile;
 
 struct ArgOptions {
     pub save_mode: SaveMode,
     pub filter: Box<dyn Fn(&Particle) -> bool>,
     pub save_images: bool,
-    pub thresholds: (f64, f64, f64),
+    pub thresholds: (f64, f64, KeV),
+    /// per pixel software thresholds, low and high
+    pub threshold_maps: (Option<ThresholdMap>, Option<ThresholdMap>),
+    /// `STANDALONE_FRAME_TIME` if not given, replays default to the recorded one
+    pub frame_time: Option<Seconds>,
+    /// `STANDALONE_HIGH_VOLTAGE` if not given, replays have no bias
+    pub high_voltage: Option<Volts>,
+    pub mode: TpxMode,
+    pub source: DeviceSource,
+    pub directories: PixDirectories,
+    /// file of the pixel mask, read before measuring or written by a noise run
+    pub mask_path: Option<String>,
+    /// amount of dark frames to find noisy pixels in instead of measuring
+    pub noise_run: Option<usize>,
+    /// file the settings and frames are saved to, never the replayed one
+    pub output: String,
+}
+
+/// where the standalone reader gets its frames from
+enum DeviceSource {
+    Hardware,
+    Simulated(SimRates),
+    Replay(String, ReplayPacing),
 }
 
 const THRESHOLD_MIN_DEFAULT: f64 = 0.0;
 const THRESHOLD_MAX_DEFAULT: f64 = 0.0;
-const THRESHOLD_PIX_DEFAULT: f64 = 0.2;
-const HIGH_VOLTAGE_DEFAULT: f64 = 50.0;
-const FRAME_TIME_DEFAULT: f64 = 2.0;
+/// the standalone reader measures shorter frames than the daemon
+const STANDALONE_FRAME_TIME: Seconds = Seconds(0.5);
+/// the standalone reader biases the sensor higher than the daemon
+const STANDALONE_HIGH_VOLTAGE: Volts = Volts(50.0);
+/// where a noise run writes the mask to if no `--mask` is given
+const MASK_PATH_DEFAULT: &str = "mask.txt";
+/// where the frames are saved to if no `--output` is given
+const OUTPUT_PATH_DEFAULT: &str = "log.txt";
+/// replays save elsewhere by default, they usually replay `log.txt`
+const REPLAY_OUTPUT_PATH_DEFAULT: &str = "replay.txt";
 
-fn main() -> () {
+fn main() {
     let mut standalone = false;
     let mut save_mode = SaveMode::AlmostJson;
     let mut filter: Box<dyn Fn(&Particle) -> bool> = Box::new(|_particle| true);
     let mut save_images = false;
-    let mut threshold_pix = THRESHOLD_PIX_DEFAULT;
+    let mut threshold_pix = THRESHOLD_DEFAULT;
+    let mut frame_time = None;
+    let mut high_voltage = None;
     let mut threshold_min = THRESHOLD_MIN_DEFAULT;
     let mut threshold_max = THRESHOLD_MAX_DEFAULT;
+    let mut threshold_maps = (None, None);
+    let mut mode = TpxMode::default();
+    let mut simulate = false;
+    let mut sim_rates = SimRates::default();
+    let mut replay = None;
+    let mut replay_pacing = ReplayPacing::Realtime;
+    let mut directories = PixDirectories::default();
+    let mut profiles = ProfileStore::default();
+    let mut mask_path = None;
+    let mut noise_run = None;
+    let mut output = None;
 
-    let mut args = std::env::args();
+    // skip the executable path
+    let mut args = std::env::args().skip(1);
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "--standalone" | "-S" => standalone = true,
@@ -63,9 +115,25 @@ fn main() -> () {
                 threshold_pix = args
                     .next()
                     .expect("Empty flag set for --threshold-pix")
-                    .parse::<f64>()
+                    .parse::<KeV>()
                     .expect("Invalid flag set for --threshold-pix");
             }
+            "--frame-time" => {
+                frame_time = Some(
+                    args.next()
+                        .expect("Empty flag set for --frame-time")
+                        .parse::<Seconds>()
+                        .expect("Invalid flag set for --frame-time"),
+                );
+            }
+            "--high-voltage" => {
+                high_voltage = Some(
+                    args.next()
+                        .expect("Empty flag set for --high-voltage")
+                        .parse::<Volts>()
+                        .expect("Invalid flag set for --high-voltage"),
+                );
+            }
             "--threshold-min" => {
                 threshold_min = args
                     .next()
@@ -80,91 +148,325 @@ fn main() -> () {
                     .parse::<f64>()
                     .expect("Invalid flag set for --threshold");
             }
+            "--threshold-min-map" => {
+                threshold_maps.0 = Some(
+                    ThresholdMap::load(
+                        args.next().expect("Empty flag set for --threshold-min-map"),
+                    )
+                    .expect("Invalid flag set for --threshold-min-map"),
+                );
+            }
+            "--threshold-max-map" => {
+                threshold_maps.1 = Some(
+                    ThresholdMap::load(
+                        args.next().expect("Empty flag set for --threshold-max-map"),
+                    )
+                    .expect("Invalid flag set for --threshold-max-map"),
+                );
+            }
+            "--mode" => {
+                mode = args
+                    .next()
+                    .expect("Empty flag set for --mode")
+                    .to_ascii_lowercase()
+                    .parse::<TpxMode>()
+                    .expect("Invalid flag set for --mode");
+            }
+            "--simulate" => simulate = true,
+            "--sim-rates" => {
+                sim_rates = args
+                    .next()
+                    .expect("Empty flag set for --sim-rates")
+                    .parse::<SimRates>()
+                    .expect("Invalid flag set for --sim-rates");
+            }
+            "--replay" => {
+                replay = Some(args.next().expect("Empty flag set for --replay"));
+            }
+            "--replay-fast" => replay_pacing = ReplayPacing::Fast,
+            "--config-dir" => {
+                directories.config = args.next().expect("Empty flag set for --config-dir").into();
+            }
+            "--log-dir" => {
+                directories.log = args.next().expect("Empty flag set for --log-dir").into();
+            }
+            "--mask" => {
+                mask_path = Some(args.next().expect("Empty flag set for --mask"));
+            }
+            "--noise-run" => {
+                noise_run = Some(
+                    args.next()
+                        .expect("Empty flag set for --noise-run")
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&frames| frames > 0)
+                        .expect("Invalid flag set for --noise-run"),
+                );
+            }
+            "--output" | "-O" => {
+                output = Some(args.next().expect("Empty flag set for --output"));
+            }
+            "--profile-dir" => {
+                profiles.dir = args
+                    .next()
+                    .expect("Empty flag set for --profile-dir")
+                    .into();
+            }
             _ => eprintln!("Invalid flag: '{}'", arg),
         }
     }
 
     if standalone {
+        let output = output.unwrap_or_else(|| match replay {
+            Some(_) => REPLAY_OUTPUT_PATH_DEFAULT.to_string(),
+            None => OUTPUT_PATH_DEFAULT.to_string(),
+        });
         let arg_options: ArgOptions = ArgOptions {
             save_mode,
             filter,
             save_images,
             thresholds: (threshold_min, threshold_max, threshold_pix),
+            threshold_maps,
+            frame_time,
+            high_voltage,
+            mode,
+            source: match (replay, simulate) {
+                (Some(path), _) => DeviceSource::Replay(path, replay_pacing),
+                (None, true) => DeviceSource::Simulated(sim_rates),
+                (None, false) => DeviceSource::Hardware,
+            },
+            directories,
+            mask_path,
+            noise_run,
+            output,
         };
         start_standalone_reader(arg_options);
-        return;
     } else {
-        library::start_library();
-        return;
+        library::start_library(&directories, &profiles);
     }
 }
 
 fn start_standalone_reader(options: ArgOptions) {
-    let handle = api::handle::PixHandle::new();
-    println!("[info]Device count: {}", handle.get_device_count());
-
-    let builder = api::handle::DeviceBuilder::new(0)
-        .frame_time(0.5)
-        .hardware_threshold(options.thresholds.2);
+    // the handle has to outlive the device
+    let mut _handle = None;
+    let mut device: Box<dyn Device> = match &options.source {
+        DeviceSource::Hardware => {
+            let handle = api::handle::PixHandle::new(&options.directories).unwrap_or_else(|why| {
+                eprintln!("[err]Failed to initialize pxcore: {why}");
+                std::process::exit(1);
+            });
+            match handle.list_devices() {
+                Ok(devices) => {
+                    println!("[info]Device count: {}", devices.len());
+                    for info in devices {
+                        println!("[info]Found {info:?}");
+                    }
+                }
+                result => result.ignore_error(),
+            }
 
-    let mut device = handle.get_device(builder).unwrap();
+            let mut builder = api::handle::DeviceBuilder::new(0)
+                .frame_time(options.frame_time.unwrap_or(STANDALONE_FRAME_TIME))
+                .hardware_threshold(options.thresholds.2)
+                .default_high_voltage(STANDALONE_HIGH_VOLTAGE)
+                .mode(options.mode);
+            if let Some(voltage) = options.high_voltage {
+                builder = builder.high_voltage(voltage);
+            }
 
-    let max_voltage = match device.get_voltage_range() {
-        Ok((_min, max)) => max,
-        Err(_) => 80.0,
+            let (device, ignored) = handle.get_device(builder).unwrap_or_else(|why| {
+                eprintln!("[err]Failed to get device: {why}");
+                std::process::exit(1);
+            });
+            for why in ignored {
+                eprintln!("[err]{why}");
+            }
+            _handle = Some(handle);
+            device
+        }
+        DeviceSource::Simulated(rates) => {
+            println!("[info]Using simulated device");
+            let mut device = SimDevice::new(*rates);
+            let frame_time = options.frame_time.unwrap_or(STANDALONE_FRAME_TIME);
+            exit_on_error(device.set_frame_time(frame_time), "frame time");
+            match options.high_voltage {
+                Some(voltage) => exit_on_error(device.set_high_voltage(voltage), "bias"),
+                None => device
+                    .set_high_voltage(STANDALONE_HIGH_VOLTAGE)
+                    .ignore_error(),
+            }
+            device.set_threshold(options.thresholds.2).ignore_error();
+            device.set_mode(options.mode).ignore_error();
+            Box::new(device)
+        }
+        DeviceSource::Replay(path, pacing) => {
+            // appending to the replayed file would replay the frames again
+            if is_same_file(path, &options.output) {
+                eprintln!("[err]Refusing to save the replay of {path} to itself, set --output");
+                std::process::exit(1);
+            }
+            println!("[info]Replaying {path}");
+            let mut device = ReplayDevice::open(path, *pacing).expect("Failed to open replay file");
+            if let Some(frame_time) = options.frame_time {
+                exit_on_error(device.set_frame_time(frame_time), "frame time");
+            }
+            Box::new(device)
+        }
     };
-    println!("[info]Found max voltage of {}V", max_voltage);
-    device.set_high_voltage(HIGH_VOLTAGE_DEFAULT).ignore_error();
+
+    if let Ok((min, max)) = device.get_voltage_range() {
+        println!("[info]Found bias range of {min} - {max}");
+    }
 
     device.set_software_high_threshold(options.thresholds.1);
     device.set_software_low_threshold(options.thresholds.0);
+    for map in [&options.threshold_maps.0, &options.threshold_maps.1]
+        .into_iter()
+        .flatten()
+    {
+        exit_on_error(
+            map.check_dimensions(device.get_dimensions()),
+            "threshold map",
+        );
+    }
+    device.thresholds_mut().low_map = options.threshold_maps.0.clone();
+    device.thresholds_mut().high_map = options.threshold_maps.1.clone();
+
+    if let Some(frames) = options.noise_run {
+        let path = options.mask_path.as_deref().unwrap_or(MASK_PATH_DEFAULT);
+        let mask = find_noisy_pixels(device.as_ref(), frames);
+        println!("[info]Masked {} noisy pixels", mask.len());
+        if let Err(why) = mask.save(path) {
+            eprintln!("[err]Failed to save mask to {path}: {why}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mask = match &options.mask_path {
+        Some(path) => PixelMask::load(path).unwrap_or_else(|why| {
+            eprintln!("[err]Failed to load mask from {path}: {why}");
+            std::process::exit(1);
+        }),
+        None => PixelMask::default(),
+    };
+
+    // every saved run starts with the settings it was measured with
+    save_settings(&options.output, &device.settings()).unwrap();
 
     let mut particles_found = 0;
+    // pxcore only keeps the image to save around for single frame measurements
+    let batch_size = match options.save_images {
+        true => 1,
+        false => Frame::batch_size(device.as_ref()),
+    };
     loop {
-        let image_buffer = device.capture_image();
-        if image_buffer.is_err() {
-            image_buffer.ignore_error();
-            continue;
+        let mut live_time = Vec::new();
+        let result = Frame::capture_batch(device.as_ref(), batch_size, |mut frame| {
+            live_time.extend(frame.live_time);
+            mask.apply(&mut frame);
+            process_frame(&mut frame, device.as_ref(), &options, &mut particles_found);
+            ControlFlow::Continue(())
+        });
+        if !live_time.is_empty() {
+            let average = live_time.iter().sum::<f64>() / live_time.len() as f64;
+            println!("[info]Live time {:.1}%", average * 100.0);
         }
-        let image_buffer = image_buffer.unwrap();
+        match result {
+            Ok(()) => {}
+            Err(why) if why == PxcErr::ReplayFinished => {
+                println!("[info]Replay finished");
+                return;
+            }
+            result => result.ignore_error(),
+        }
+    }
+}
 
-        let image = image_buffer
-            .chunks(device.get_dimensions().0 as usize)
-            .map(|buf| buf.to_vec())
-            .collect::<Vec<_>>();
+/// captures dark frames until the noise run is done
+fn find_noisy_pixels(device: &dyn Device, frames: usize) -> PixelMask {
+    println!("[info]Capturing {frames} dark frames");
+    let mut run = NoiseRun::new(frames);
+    let mut mask = None;
+    while mask.is_none() {
+        let result = Frame::capture_batch(device, Frame::batch_size(device), |frame| {
+            mask = run.add(&frame);
+            match mask {
+                Some(_) => ControlFlow::Break(()),
+                None => ControlFlow::Continue(()),
+            }
+        });
+        match result {
+            Ok(()) => {}
+            // fewer dark frames than asked for
+            Err(why) if why == PxcErr::ReplayFinished => break,
+            result => result.ignore_error(),
+        }
+    }
+    mask.unwrap_or_else(|| run.mask())
+}
 
-        let mut frame = data_worker::frame::Frame::new(image);
-        // this is the kernel size
-        // yes it is a magic number
-        // no i do not care
-        frame.count_particles(12);
+/// exits if a setting given on the command line was rejected by the device
+fn exit_on_error(result: PxcResult<()>, setting: &str) {
+    if let Err(why) = result {
+        eprintln!("[err]Invalid {setting}: {why}");
+        std::process::exit(1);
+    }
+}
 
-        for particle in frame.get_particles_mut() {
-            particle.calculate_type();
-        }
+/// looks for particles in the frame and saves it if one passes the filter
+fn process_frame(
+    frame: &mut Frame,
+    device: &dyn Device,
+    options: &ArgOptions,
+    particles_found: &mut usize,
+) {
+    // this is the kernel size
+    // yes it is a magic number
+    // no i do not care
+    frame.count_particles(12);
+
+    for particle in frame.get_particles_mut() {
+        particle.calculate_type();
+    }
 
-        for particle in frame.get_particles() {
-            if (options.filter)(&particle) {
-                println!(
+    for particle in frame.get_particles() {
+        if (options.filter)(&particle) {
+            match particle.energy {
+                Some(energy) => println!(
+                    "[info]{}Found particle {:?} with {energy:.1}keV",
+                    particles_found, particle.particle_type
+                ),
+                None => println!(
                     "[info]{}Found particle {:?}",
                     particles_found, particle.particle_type
-                );
-                save_frame("log.txt", frame.clone(), options.save_mode).unwrap();
-                if options.save_images
-                    && let Err(why) = device.save_last_frame(&format!(
-                        "particle{particles_found}{:?}.png",
-                        &particle.particle_type
-                    ))
-                {
-                    eprintln!("[err]Failed to save frame: {why:?}");
-                }
-                particles_found += 1;
-                break;
+                ),
+            }
+            save_frame(&options.output, frame.clone(), options.save_mode).unwrap();
+            if options.save_images
+                && let Err(why) = device.save_last_frame(&format!(
+                    "particle{particles_found}{:?}.png",
+                    &particle.particle_type
+                ))
+            {
+                eprintln!("[err]Failed to save frame: {why:?}");
             }
+            *particles_found += 1;
+            break;
         }
     }
 }
 
+/// whether both paths lead to the same existing file
+fn is_same_file(first: impl AsRef<Path>, second: impl AsRef<Path>) -> bool {
+    match (
+        first.as_ref().canonicalize(),
+        second.as_ref().canonicalize(),
+    ) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false,
+    }
+}
+
 #[derive(Clone, Copy)]
 enum SaveMode {
     AlmostJson,
@@ -199,6 +501,15 @@ fn save_frame(path: impl AsRef<Path>, frame: Frame, mode: SaveMode) -> std::io::
     Ok(())
 }
 
+fn save_settings(path: impl AsRef<Path>, settings: &DeviceSettings) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{SETTINGS_PREFIX}{}", settings.to_json())?;
+    file.flush()
+}
+
 #[allow(clippy::single_match)]
 fn make_filter(to_filter: Vec<String>) -> Box<dyn Fn(&Particle) -> bool> {
     Box::new(move |particle| {
@@ -229,10 +540,12 @@ mod tests {
         let muon = Particle {
             particle_type: ParticleType::PossibleMuon(5),
             positions: vec![],
+            energy: None,
         };
         let diff_particle = Particle {
             particle_type: ParticleType::Unknown,
             positions: vec![],
+            energy: None,
         };
 
         assert!(filter(&muon));
@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// Named sets of daemon settings stored on disk, one directory per detector serial number
+///
+/// a profile is a text file with one `key value` pair per line, the same as in `set`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileStore {
+    pub dir: PathBuf,
+}
+
+impl Default for ProfileStore {
+    /// `PIXET_PROFILE_DIR`, or `profiles` in the working directory if it is unset
+    fn default() -> Self {
+        let dir = std::env::var_os("PIXET_PROFILE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("profiles"));
+        Self { dir }
+    }
+}
+
+impl ProfileStore {
+    /// profile applied when a detector is added
+    pub const DEFAULT: &str = "default";
+
+    pub fn exists(&self, serial: u32, name: &str) -> bool {
+        self.path(serial, name).is_ok_and(|path| path.is_file())
+    }
+
+    /// writes the settings as the profile `name`, replacing it if it exists
+    pub fn save(&self, serial: u32, name: &str, settings: &[(String, String)]) -> io::Result<()> {
+        let path = self.path(serial, name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::File::create(path)?;
+        for (key, value) in settings {
+            writeln!(file, "{key} {value}")?;
+        }
+        Ok(())
+    }
+
+    pub fn load(&self, serial: u32, name: &str) -> io::Result<Vec<(String, String)>> {
+        let file = fs::File::open(self.path(serial, name)?)?;
+        let mut settings = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(char::is_whitespace) {
+                Some((key, value)) => settings.push((key.to_owned(), value.trim().to_owned())),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Missing value of {line}"),
+                    ));
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    /// names may only contain letters, digits, `-` and `_`, so they stay inside the directory
+    fn path(&self, serial: u32, name: &str) -> io::Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        match valid {
+            true => Ok(self.dir.join(serial.to_string()).join(name)),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid profile name '{name}'"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_are_saved_per_serial() {
+        let store = ProfileStore {
+            dir: std::env::temp_dir().join(format!("pixet_profiles_{}", std::process::id())),
+        };
+        let settings = vec![
+            ("frame-time".to_owned(), "0.5s".to_owned()),
+            ("high-voltage".to_owned(), "60V".to_owned()),
+        ];
+        store.save(12345, "cosmics", &settings).unwrap();
+
+        assert!(store.exists(12345, "cosmics"));
+        assert!(!store.exists(54321, "cosmics"));
+        assert_eq!(store.load(12345, "cosmics").unwrap(), settings);
+        assert!(store.load(54321, "cosmics").is_err());
+        assert!(store.save(12345, "../cosmics", &settings).is_err());
+        assert!(!store.exists(12345, ""));
+
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+}